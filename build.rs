@@ -0,0 +1,32 @@
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    if env::var_os("CARGO_FEATURE_BUILTIN_LABELS").is_some() {
+        write_builtin_labels();
+    }
+}
+
+/// Turn `bgm_hashes.txt` into a slice of `(Hash40, &str)` pairs, hashed by the const `hash40`
+fn write_builtin_labels() {
+    println!("cargo:rerun-if-changed=bgm_hashes.txt");
+
+    let contents = fs::read_to_string("bgm_hashes.txt").unwrap();
+
+    let mut out = String::from("&[\n");
+    for label in contents.lines().map(str::trim) {
+        if label.is_empty() || label.starts_with('#') {
+            continue
+        }
+
+        writeln!(out, "    (crate::hash40({:?}), {:?}),", label, label).unwrap();
+    }
+    out.push_str("]\n");
+
+    let out_dir = env::var_os("OUT_DIR").unwrap();
+    fs::write(Path::new(&out_dir).join("builtin_labels.rs"), out).unwrap();
+}
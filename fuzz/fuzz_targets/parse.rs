@@ -0,0 +1,26 @@
+//! Run with `cargo fuzz run parse -- -malloc_limit_mb=64` to also fail on any allocation larger
+//! than the input could justify.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sound_label_info::view::SliView;
+use sound_label_info::SliFile;
+
+fuzz_target!(|data: &[u8]| {
+    let file = SliFile::from_bytes(data);
+    let view = SliView::new(data);
+
+    // both parsers must agree on what is a valid file, and on its contents. Fuzz inputs are far
+    // too small to reach the default entry limit, which only SliFile enforces.
+    match (file, view) {
+        (Ok(file), Ok(view)) => {
+            assert_eq!(file.version(), view.version());
+            assert!(view.iter().eq(file.entries().iter().copied()));
+            assert_eq!(file.trailing(), view.trailing());
+            assert_eq!(file.to_bytes(), data);
+        }
+        (Err(_), Err(_)) => (),
+        (file, view) => panic!("SliFile gave {:?} but SliView gave {:?}", file.is_ok(), view.is_ok()),
+    }
+});
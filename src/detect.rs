@@ -0,0 +1,159 @@
+//! Telling `.sli` files apart from the text formats they are converted to by their contents, so a
+//! corrupted file is reported as such instead of being handed to the wrong parser.
+
+use std::fmt;
+use std::str;
+
+/// The magic every `.sli` file starts with
+pub const SLI_MAGIC: &[u8; 4] = b"SLI\x00";
+
+/// A format found by [`detect`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Sli,
+    Yaml,
+    Json,
+    Toml,
+    Csv,
+}
+
+/// Why [`detect`] could not recognize a format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DetectError {
+    /// There are no bytes, or only whitespace
+    Empty,
+
+    /// Not a `.sli` file, and not text either because of the invalid UTF-8 at `offset`
+    Binary {
+        /// Up to the first 4 bytes, for comparing against the expected magic
+        start: Vec<u8>,
+        offset: usize,
+    },
+
+    /// Valid text, but not in any of the supported formats
+    UnknownText {
+        /// The first line which is not blank or a comment
+        first_line: String,
+    },
+}
+
+impl Format {
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Sli => "sli",
+            Format::Yaml => "yaml",
+            Format::Json => "json",
+            Format::Toml => "toml",
+            Format::Csv => "csv",
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Guess the format of a file from its contents.
+///
+/// Anything starting with the `.sli` magic is reported as [`Format::Sli`], even if it is too short
+/// to hold a header, so that truncated files are reported by the binary parser. Text formats are
+/// told apart by their first line which is not blank or a `#` comment.
+pub fn detect(bytes: &[u8]) -> Result<Format, DetectError> {
+    if bytes.starts_with(SLI_MAGIC) {
+        return Ok(Format::Sli)
+    }
+
+    let text = match str::from_utf8(bytes) {
+        Ok(text) => text.trim_start_matches('\u{feff}'),
+        Err(err) => {
+            return Err(DetectError::Binary {
+                start: bytes.iter().take(SLI_MAGIC.len()).copied().collect(),
+                offset: err.valid_up_to(),
+            })
+        }
+    };
+
+    let first_line = match text.lines().map(str::trim).find(|line| !line.is_empty() && !line.starts_with('#')) {
+        Some(line) => line,
+        None => return Err(DetectError::Empty),
+    };
+
+    if first_line.starts_with('{') || (first_line.starts_with('[') && !is_toml_table_header(first_line)) {
+        Ok(Format::Json)
+    } else if is_toml_table_header(first_line) || is_toml_key_value(first_line) {
+        Ok(Format::Toml)
+    } else if first_line == "---" || first_line.starts_with("- ") || first_line == "-" || is_yaml_key(first_line) {
+        Ok(Format::Yaml)
+    } else if first_line.contains(',') {
+        Ok(Format::Csv)
+    } else {
+        Err(DetectError::UnknownText { first_line: first_line.to_owned() })
+    }
+}
+
+fn is_bare_key(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+/// `[table]` or `[[array.of.tables]]`, as opposed to a JSON array
+fn is_toml_table_header(line: &str) -> bool {
+    let inner = line.strip_prefix("[[").and_then(|line| line.strip_suffix("]]"))
+        .or_else(|| line.strip_prefix('[').and_then(|line| line.strip_suffix(']')));
+
+    matches!(inner, Some(key) if is_bare_key(key.trim()))
+}
+
+fn is_toml_key_value(line: &str) -> bool {
+    matches!(line.split_once('='), Some((key, _)) if is_bare_key(key.trim()))
+}
+
+fn is_yaml_key(line: &str) -> bool {
+    match line.split_once(':') {
+        Some((key, value)) => is_bare_key(key) && (value.is_empty() || value.starts_with(' ')),
+        None => false,
+    }
+}
+
+impl fmt::Display for DetectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DetectError::Empty => write!(f, "the file is empty"),
+            DetectError::Binary { start, offset } => {
+                write!(f, "it starts with")?;
+                for byte in start {
+                    write!(f, " {:02x}", byte)?;
+                }
+                write!(f, " instead of SLI\\0 and is not text either, with invalid UTF-8 at byte {}", offset)
+            }
+            DetectError::UnknownText { first_line } => {
+                write!(f, "it is text, but not yaml, json, toml or csv, starting with {:?}", first_line)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DetectError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(b"SLI\x00\x01\x00\x00\x00"), Ok(Format::Sli));
+        assert_eq!(detect(b"SLI\x00"), Ok(Format::Sli));
+        assert_eq!(detect(b"---\n- 1\n"), Ok(Format::Yaml));
+        assert_eq!(detect(b"version: 1\nentries: []\n"), Ok(Format::Yaml));
+        assert_eq!(detect(b"[\n  1,\n  []\n]"), Ok(Format::Json));
+        assert_eq!(detect(b"{\"version\": 1}"), Ok(Format::Json));
+        assert_eq!(detect(b"# comment\nversion = 1\n\n[[entries]]\n"), Ok(Format::Toml));
+        assert_eq!(detect(b"[[entries]]\ntone_name = \"a\"\n"), Ok(Format::Toml));
+        assert_eq!(detect(b"\xef\xbb\xbftone_name,nus3bank_id,tone_id\n"), Ok(Format::Csv));
+
+        assert_eq!(detect(b" \n# only a comment\n"), Err(DetectError::Empty));
+        assert_eq!(detect(b"SLJ\x00\xff"), Err(DetectError::Binary { start: b"SLJ\x00".to_vec(), offset: 4 }));
+        assert!(matches!(detect(b"hello world"), Err(DetectError::UnknownText { .. })));
+    }
+}
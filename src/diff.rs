@@ -0,0 +1,353 @@
+//! Comparing two [`SliFile`]s by `tone_name` rather than byte for byte.
+
+use crate::{Entry, Hash40, LabelResolver, SliFile, WithLabels};
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// The changes needed to turn one [`SliFile`] into another, created by [`SliFile::diff`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SliDiff {
+    /// Entries only in the new file, in the new file's order
+    pub added: Vec<Entry>,
+
+    /// Entries only in the old file, in the old file's order
+    pub removed: Vec<Entry>,
+
+    /// Entries whose `nus3bank_id` or `tone_id` changed, in the new file's order
+    pub modified: Vec<ModifiedEntry>,
+
+    /// Entries in both files which changed position relative to the others
+    pub moved: Vec<MovedEntry>,
+
+    /// The header version, if it changed
+    pub version: Option<Changed<u32>>,
+
+    /// The bytes after the last entry, if they changed
+    pub trailing: Option<Changed<Vec<u8>>>,
+}
+
+/// A value which differs between the two files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Changed<T> {
+    pub before: T,
+    pub after: T,
+}
+
+/// An entry present in both files with different ids
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModifiedEntry {
+    pub tone_name: Hash40,
+    pub before: Entry,
+    pub after: Entry,
+}
+
+/// An entry present in both files at a different position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MovedEntry {
+    pub tone_name: Hash40,
+    pub old_index: usize,
+    pub new_index: usize,
+}
+
+impl SliDiff {
+    /// Whether the two files contain the same entries, ignoring order, and have the same header
+    /// version and trailing bytes
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.modified.is_empty()
+            && self.version.is_none()
+            && self.trailing.is_none()
+    }
+
+    /// Whether the only differences between the two files are the order of their entries
+    pub fn is_order_only(&self) -> bool {
+        self.is_empty() && !self.moved.is_empty()
+    }
+
+    /// Pair this diff with the labels to use for `tone_name`s when displaying or serializing it
+    pub fn with_labels<'a, L: LabelResolver + ?Sized>(&'a self, labels: &'a L) -> WithLabels<'a, Self, L> {
+        WithLabels::new(self, labels)
+    }
+}
+
+impl SliFile {
+    /// Compare this file against a newer version of it. Entries are matched by `tone_name`, using
+    /// the first entry for any duplicated name.
+    pub fn diff(&self, new: &SliFile) -> SliDiff {
+        let old_positions = first_positions(self.entries());
+        let new_positions = first_positions(new.entries());
+
+        let mut diff = SliDiff::default();
+
+        if self.version() != new.version() {
+            diff.version = Some(Changed { before: self.version(), after: new.version() });
+        }
+        if self.trailing() != new.trailing() {
+            diff.trailing = Some(Changed { before: self.trailing().to_vec(), after: new.trailing().to_vec() });
+        }
+
+        for (i, entry) in self.entries().iter().enumerate() {
+            if old_positions[&entry.tone_name] == i && !new_positions.contains_key(&entry.tone_name) {
+                diff.removed.push(*entry);
+            }
+        }
+
+        // (old index, new index) of every entry in both files, in new order
+        let mut common = Vec::new();
+        for (i, entry) in new.entries().iter().enumerate() {
+            if new_positions[&entry.tone_name] != i {
+                continue
+            }
+
+            match old_positions.get(&entry.tone_name) {
+                Some(&old_index) => {
+                    let before = self.entries()[old_index];
+                    if before != *entry {
+                        diff.modified.push(ModifiedEntry { tone_name: entry.tone_name, before, after: *entry });
+                    }
+                    common.push((old_index, i));
+                }
+                None => diff.added.push(*entry),
+            }
+        }
+
+        // anything outside the longest run of entries that kept their relative order has moved
+        let old_indices: Vec<_> = common.iter().map(|&(old_index, _)| old_index).collect();
+        let in_order = longest_increasing_subsequence(&old_indices);
+        diff.moved = common.iter()
+            .enumerate()
+            .filter(|(i, _)| !in_order[*i])
+            .map(|(_, &(old_index, new_index))| MovedEntry {
+                tone_name: new.entries()[new_index].tone_name,
+                old_index,
+                new_index,
+            })
+            .collect();
+
+        diff
+    }
+}
+
+fn first_positions(entries: &[Entry]) -> HashMap<Hash40, usize> {
+    let mut positions = HashMap::with_capacity(entries.len());
+    for (i, entry) in entries.iter().enumerate() {
+        positions.entry(entry.tone_name).or_insert(i);
+    }
+
+    positions
+}
+
+/// Mark which values are part of a longest strictly increasing subsequence
+fn longest_increasing_subsequence(values: &[usize]) -> Vec<bool> {
+    // tails[k] is the index of the smallest tail of an increasing run of length k + 1
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous = vec![None; values.len()];
+
+    for (i, value) in values.iter().enumerate() {
+        let k = tails.partition_point(|&tail| values[tail] < *value);
+        previous[i] = if k > 0 { Some(tails[k - 1]) } else { None };
+        if k == tails.len() {
+            tails.push(i);
+        } else {
+            tails[k] = i;
+        }
+    }
+
+    let mut in_sequence = vec![false; values.len()];
+    let mut next = tails.last().copied();
+    while let Some(i) = next {
+        in_sequence[i] = true;
+        next = previous[i];
+    }
+
+    in_sequence
+}
+
+impl<L: LabelResolver + ?Sized> fmt::Display for WithLabels<'_, SliDiff, L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (diff, labels) = (self.value, self.labels);
+
+        if let Some(version) = &diff.version {
+            writeln!(f, "~ version {} -> {}", version.before, version.after)?;
+        }
+        if let Some(trailing) = &diff.trailing {
+            writeln!(f, "~ trailing bytes {} -> {}", TrailingHex(&trailing.before), TrailingHex(&trailing.after))?;
+        }
+        for entry in &diff.removed {
+            writeln!(
+                f, "- {} nus3bank_id {} tone_id {}",
+                labels.resolve_or_hex(entry.tone_name), entry.nus3bank_id, entry.tone_id
+            )?;
+        }
+        for entry in &diff.added {
+            writeln!(
+                f, "+ {} nus3bank_id {} tone_id {}",
+                labels.resolve_or_hex(entry.tone_name), entry.nus3bank_id, entry.tone_id
+            )?;
+        }
+        for modified in &diff.modified {
+            writeln!(
+                f, "~ {} nus3bank_id {} -> {} tone_id {} -> {}",
+                labels.resolve_or_hex(modified.tone_name),
+                modified.before.nus3bank_id, modified.after.nus3bank_id,
+                modified.before.tone_id, modified.after.tone_id,
+            )?;
+        }
+        for moved in &diff.moved {
+            writeln!(
+                f, "> {} moved from entry {} to {}",
+                labels.resolve_or_hex(moved.tone_name), moved.old_index, moved.new_index
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Trailing bytes as hex, or `none` if there are none
+pub(crate) struct TrailingHex<'a>(pub(crate) &'a [u8]);
+
+impl fmt::Display for TrailingHex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str("none")
+        }
+
+        self.0.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+impl fmt::Display for SliDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.with_labels(&()).fmt(f)
+    }
+}
+
+#[cfg(feature = "derive_serde")]
+mod serde_impls {
+    use super::{Changed, ModifiedEntry, MovedEntry, SliDiff, TrailingHex};
+    use crate::{Entry, LabelResolver, WithLabels};
+    use serde::ser::{Serialize, Serializer, SerializeSeq, SerializeStruct};
+
+    /// The ids of an entry, without its `tone_name`
+    struct Ids<'a>(&'a Entry);
+
+    impl Serialize for Ids<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut ids = serializer.serialize_struct("Ids", 2)?;
+            ids.serialize_field("nus3bank_id", &self.0.nus3bank_id)?;
+            ids.serialize_field("tone_id", &self.0.tone_id)?;
+            ids.end()
+        }
+    }
+
+    impl<L: LabelResolver + ?Sized> Serialize for WithLabels<'_, ModifiedEntry, L> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut modified = serializer.serialize_struct("ModifiedEntry", 3)?;
+            modified.serialize_field("tone_name", &WithLabels::new(&self.value.tone_name, self.labels))?;
+            modified.serialize_field("before", &Ids(&self.value.before))?;
+            modified.serialize_field("after", &Ids(&self.value.after))?;
+            modified.end()
+        }
+    }
+
+    impl<L: LabelResolver + ?Sized> Serialize for WithLabels<'_, MovedEntry, L> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut moved = serializer.serialize_struct("MovedEntry", 3)?;
+            moved.serialize_field("tone_name", &WithLabels::new(&self.value.tone_name, self.labels))?;
+            moved.serialize_field("old_index", &self.value.old_index)?;
+            moved.serialize_field("new_index", &self.value.new_index)?;
+            moved.end()
+        }
+    }
+
+    impl<T: Serialize> Serialize for Changed<T> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut changed = serializer.serialize_struct("Changed", 2)?;
+            changed.serialize_field("before", &self.before)?;
+            changed.serialize_field("after", &self.after)?;
+            changed.end()
+        }
+    }
+
+    /// Serializes each element of a slice paired with the same labels
+    struct LabeledSeq<'a, T, L: ?Sized>(&'a [T], &'a L);
+
+    impl<'a, T, L> Serialize for LabeledSeq<'a, T, L>
+        where L: LabelResolver + ?Sized,
+              WithLabels<'a, T, L>: Serialize,
+    {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut seq = serializer.serialize_seq(Some(self.0.len()))?;
+            for value in self.0 {
+                seq.serialize_element(&WithLabels::new(value, self.1))?;
+            }
+            seq.end()
+        }
+    }
+
+    impl<L: LabelResolver + ?Sized> Serialize for WithLabels<'_, SliDiff, L> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let (diff, labels) = (self.value, self.labels);
+
+            let trailing = diff.trailing.as_ref().map(|trailing| Changed {
+                before: TrailingHex(&trailing.before).to_string(),
+                after: TrailingHex(&trailing.after).to_string(),
+            });
+
+            let mut out = serializer.serialize_struct("SliDiff", 6)?;
+            out.serialize_field("version", &diff.version)?;
+            out.serialize_field("trailing", &trailing)?;
+            out.serialize_field("added", &LabeledSeq(&diff.added, labels))?;
+            out.serialize_field("removed", &LabeledSeq(&diff.removed, labels))?;
+            out.serialize_field("modified", &LabeledSeq(&diff.modified, labels))?;
+            out.serialize_field("moved", &LabeledSeq(&diff.moved, labels))?;
+            out.end()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash40;
+
+    #[test]
+    fn test_diff() {
+        let old = SliFile::new(1, vec![
+            Entry::new(hash40("a"), 1, 0),
+            Entry::new(hash40("b"), 2, 0),
+            Entry::new(hash40("c"), 3, 0),
+            Entry::new(hash40("d"), 4, 0),
+        ]);
+        let new = SliFile::new(1, vec![
+            Entry::new(hash40("b"), 2, 0),
+            Entry::new(hash40("c"), 3, 1),
+            Entry::new(hash40("a"), 1, 0),
+            Entry::new(hash40("e"), 5, 0),
+        ]);
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.added, [Entry::new(hash40("e"), 5, 0)]);
+        assert_eq!(diff.removed, [Entry::new(hash40("d"), 4, 0)]);
+        assert_eq!(diff.modified, [ModifiedEntry {
+            tone_name: hash40("c"),
+            before: Entry::new(hash40("c"), 3, 0),
+            after: Entry::new(hash40("c"), 3, 1),
+        }]);
+        assert_eq!(diff.moved, [MovedEntry { tone_name: hash40("a"), old_index: 0, new_index: 2 }]);
+
+        assert!(old.diff(&old).is_empty());
+        assert_eq!((diff.version, &diff.trailing), (None, &None));
+
+        let mut new = old.clone();
+        new.set_version(2);
+        new.set_trailing(vec![0xab]);
+        let diff = old.diff(&new);
+        assert!(!diff.is_empty());
+        assert_eq!(diff.version, Some(Changed { before: 1, after: 2 }));
+        assert_eq!(diff.to_string(), "~ version 1 -> 2\n~ trailing bytes none -> ab\n");
+    }
+}
@@ -0,0 +1,121 @@
+//! Editing entries by `tone_name`, checking that the tones involved exist and that the edit does
+//! not introduce duplicates.
+
+use crate::{Entry, Hash40, LabelResolver, SliFile, WithLabels};
+
+use std::fmt;
+
+/// Why an edit to a [`SliFile`] was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditError {
+    /// No entry exists for the tone
+    Missing(Hash40),
+
+    /// An entry already exists for the tone
+    AlreadyExists(Hash40),
+
+    /// Every `nus3bank_id` or `tone_id` above the highest one in use is taken
+    NoFreeIds,
+}
+
+impl EditError {
+    /// Pair this error with the labels to use for `tone_name`s when displaying it
+    pub fn with_labels<'a, L: LabelResolver + ?Sized>(&'a self, labels: &'a L) -> WithLabels<'a, Self, L> {
+        WithLabels::new(self, labels)
+    }
+}
+
+impl SliFile {
+    /// Change the `tone_name` of the entry for `from` to `to`, keeping its ids and position
+    pub fn rename(&mut self, from: Hash40, to: Hash40) -> Result<(), EditError> {
+        if from != to && self.contains(to) {
+            return Err(EditError::AlreadyExists(to))
+        }
+
+        let entry = self.get_mut(from).ok_or(EditError::Missing(from))?;
+        entry.tone_name = to;
+
+        Ok(())
+    }
+
+    /// Exchange the `nus3bank_id` and `tone_id` of the entries for `a` and `b`, so each tone plays
+    /// the other's sound
+    pub fn swap(&mut self, a: Hash40, b: Hash40) -> Result<(), EditError> {
+        let a_entry = *self.get(a).ok_or(EditError::Missing(a))?;
+        let b_entry = *self.get(b).ok_or(EditError::Missing(b))?;
+
+        for (tone_name, ids) in [(a, b_entry), (b, a_entry)] {
+            let entry = self.get_mut(tone_name).ok_or(EditError::Missing(tone_name))?;
+            entry.nus3bank_id = ids.nus3bank_id;
+            entry.tone_id = ids.tone_id;
+        }
+
+        Ok(())
+    }
+
+    /// Append a copy of the entry for `src` named `new`, so both tones play the same sound
+    pub fn clone_entry(&mut self, src: Hash40, new: Hash40) -> Result<Entry, EditError> {
+        let entry = Entry { tone_name: new, ..*self.get(src).ok_or(EditError::Missing(src))? };
+        if self.contains(new) {
+            return Err(EditError::AlreadyExists(new))
+        }
+
+        self.insert_or_replace(entry);
+
+        Ok(entry)
+    }
+}
+
+impl<L: LabelResolver + ?Sized> fmt::Display for WithLabels<'_, EditError, L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self.value {
+            EditError::Missing(tone_name) => {
+                write!(f, "no entry for {}", self.labels.resolve_or_hex(tone_name))
+            }
+            EditError::AlreadyExists(tone_name) => {
+                write!(f, "an entry for {} already exists", self.labels.resolve_or_hex(tone_name))
+            }
+            EditError::NoFreeIds => write!(f, "no unused nus3bank_id or tone_id is left"),
+        }
+    }
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.with_labels(&()).fmt(f)
+    }
+}
+
+impl std::error::Error for EditError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash40;
+
+    #[test]
+    fn test_rename() {
+        let mut file = SliFile::new(1, vec![Entry::new(hash40("a"), 1, 0), Entry::new(hash40("b"), 2, 0)]);
+
+        file.rename(hash40("a"), hash40("c")).unwrap();
+        assert_eq!(file.entries(), &[Entry::new(hash40("c"), 1, 0), Entry::new(hash40("b"), 2, 0)]);
+        assert_eq!(file.get_by_label("c"), Some(&Entry::new(hash40("c"), 1, 0)));
+
+        assert_eq!(file.rename(hash40("a"), hash40("d")), Err(EditError::Missing(hash40("a"))));
+        assert_eq!(file.rename(hash40("b"), hash40("c")), Err(EditError::AlreadyExists(hash40("c"))));
+    }
+
+    #[test]
+    fn test_swap_and_clone() {
+        let mut file = SliFile::new(1, vec![Entry::new(hash40("a"), 1, 0), Entry::new(hash40("b"), 2, 3)]);
+
+        file.swap(hash40("a"), hash40("b")).unwrap();
+        assert_eq!(file.entries(), &[Entry::new(hash40("a"), 2, 3), Entry::new(hash40("b"), 1, 0)]);
+        assert_eq!(file.swap(hash40("a"), hash40("c")), Err(EditError::Missing(hash40("c"))));
+
+        assert_eq!(file.clone_entry(hash40("a"), hash40("c")), Ok(Entry::new(hash40("c"), 2, 3)));
+        assert_eq!(file.entries().len(), 3);
+        assert_eq!(file.clone_entry(hash40("a"), hash40("b")), Err(EditError::AlreadyExists(hash40("b"))));
+        assert_eq!(file.clone_entry(hash40("d"), hash40("e")), Err(EditError::Missing(hash40("d"))));
+    }
+}
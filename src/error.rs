@@ -0,0 +1,138 @@
+//! The error type shared by reading, writing and converting [`SliFile`](crate::SliFile)s.
+
+use crate::LabelsError;
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing failed, at `path` if the operation was on a file
+    Io {
+        path: Option<PathBuf>,
+        error: io::Error,
+    },
+
+    /// The data does not start with `SLI\0`
+    BadMagic {
+        found: [u8; 4],
+    },
+
+    /// The data ends partway through the header, or partway through the entry at `entry_index`
+    Truncated {
+        entry_index: Option<usize>,
+
+        /// The offset of the header or entry which was cut off
+        offset: u64,
+    },
+
+    /// The header claims more entries than the rest of the data has room for, so the entry table
+    /// is cut off at `offset`, the start of the first missing entry
+    CountMismatch {
+        count: u32,
+        available: u64,
+        offset: u64,
+    },
+
+    /// The header claims more entries than [`ReadLimits::max_entries`](crate::ReadLimits) allows
+    TooManyEntries {
+        count: u32,
+        max: u32,
+    },
+
+    /// The header version is not in [`KNOWN_VERSIONS`](crate::KNOWN_VERSIONS), and
+    /// [`ReadLimits::reject_unknown_versions`](crate::ReadLimits) is set
+    UnknownVersion {
+        version: u32,
+    },
+
+    /// Converting to or from a text format failed
+    Serde(String),
+
+    /// A labels file could not be loaded
+    Labels(LabelsError),
+}
+
+impl Error {
+    /// Attach the path of the file being read or written to an I/O error without one
+    pub(crate) fn at_path(self, path: &Path) -> Self {
+        match self {
+            Error::Io { path: None, error } => Error::Io { path: Some(path.to_owned()), error },
+            err => err,
+        }
+    }
+
+    /// Wrap the error of a serde data format, such as `serde_yaml::Error`
+    pub fn serde<E: fmt::Display>(error: E) -> Self {
+        Error::Serde(error.to_string())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io { path: Some(path), error } => write!(f, "{}: {}", path.display(), error),
+            Error::Io { path: None, error } => write!(f, "{}", error),
+            Error::BadMagic { found } => {
+                write!(f, "not a .sli file, expected magic SLI\\0 but found {}", found.escape_ascii())
+            }
+            Error::Truncated { entry_index: None, offset } => {
+                write!(f, "the file is truncated, the header at byte {} is cut off", offset)
+            }
+            Error::Truncated { entry_index: Some(index), offset } => {
+                write!(f, "the file is truncated, entry {} at byte {} is cut off", index, offset)
+            }
+            Error::CountMismatch { count, available, offset } => {
+                write!(
+                    f, "the file is truncated, the header claims {} entries but only {} are complete, cut off at byte {}",
+                    count, available, offset
+                )
+            }
+            Error::TooManyEntries { count, max } => {
+                write!(f, "the header claims {} entries, over the limit of {}", count, max)
+            }
+            Error::UnknownVersion { version } => write!(f, "unknown header version {}", version),
+            Error::Serde(message) => f.write_str(message),
+            Error::Labels(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { error, .. } => Some(error),
+            Error::Labels(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io { path: None, error }
+    }
+}
+
+impl From<LabelsError> for Error {
+    fn from(err: LabelsError) -> Self {
+        Error::Labels(err)
+    }
+}
+
+#[cfg(feature = "derive_serde")]
+impl serde::ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::serde(msg)
+    }
+}
+
+#[cfg(feature = "derive_serde")]
+impl serde::de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::serde(msg)
+    }
+}
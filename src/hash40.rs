@@ -1,3 +1,189 @@
+//! The 40-bit hashes used by Smash Ultimate to refer to names such as `tone_name`.
+//!
+//! A [`Hash40`] is the CRC32 of a string in the low 32 bits, with the length of the string in
+//! the byte above it.
+
+use binread::BinRead;
+use binwrite::{BinWrite, WriterOption};
+
+use crate::labels::{LabelResolver, WithLabels};
+
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{self, Write};
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+/// A hashed name, such as the `tone_name` of an [`Entry`](crate::Entry)
+#[derive(BinRead, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct Hash40(pub u64);
+
+impl Hash40 {
+    /// Hash a label, such as `"a01_smb_chijyou"`
+    pub const fn from_label(label: &str) -> Self {
+        hash40(label)
+    }
+
+    /// The raw 40-bit value
+    pub const fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    /// The length of the hashed label, stored in the upper byte
+    pub const fn length(self) -> u8 {
+        (self.0 >> 32) as u8
+    }
+
+    /// The CRC32 of the hashed label, stored in the lower 32 bits
+    pub const fn crc32(self) -> u32 {
+        self.0 as u32
+    }
+}
+
+impl From<u64> for Hash40 {
+    fn from(hash: u64) -> Self {
+        Hash40(hash)
+    }
+}
+
+impl From<Hash40> for u64 {
+    fn from(hash: Hash40) -> Self {
+        hash.0
+    }
+}
+
+/// An error from parsing a [`Hash40`] with [`FromStr`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseHash40Error {
+    /// The string was empty
+    Empty,
+
+    /// The string looked like a hex or decimal number but could not be parsed as one
+    InvalidNumber(ParseIntError),
+}
+
+impl fmt::Display for ParseHash40Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseHash40Error::Empty => write!(f, "cannot parse a Hash40 from an empty string"),
+            ParseHash40Error::InvalidNumber(err) => write!(f, "invalid Hash40: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ParseHash40Error {}
+
+impl FromStr for Hash40 {
+    type Err = ParseHash40Error;
+
+    /// Parses `0x`-prefixed hex, plain decimal, or otherwise hashes the string as a label
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            Err(ParseHash40Error::Empty)
+        } else if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            u64::from_str_radix(hex, 16)
+                .map(Hash40)
+                .map_err(ParseHash40Error::InvalidNumber)
+        } else if s.bytes().all(|b| b.is_ascii_digit()) {
+            s.parse()
+                .map(Hash40)
+                .map_err(ParseHash40Error::InvalidNumber)
+        } else {
+            Ok(hash40(s))
+        }
+    }
+}
+
+impl fmt::Display for Hash40 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#x}", self.0)
+    }
+}
+
+impl fmt::Debug for Hash40 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Hash40({:#x})", self.0)
+    }
+}
+
+impl fmt::LowerHex for Hash40 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+
+impl fmt::UpperHex for Hash40 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::UpperHex::fmt(&self.0, f)
+    }
+}
+
+impl BinWrite for Hash40 {
+    fn write_options<W: Write>(&self, writer: &mut W, options: &WriterOption) -> io::Result<()> {
+        self.0.write_options(writer, options)
+    }
+}
+
+impl Hash40 {
+    /// Look up the label for this hash using the given labels
+    pub fn label<L: LabelResolver + ?Sized>(self, labels: &L) -> Option<Cow<'_, str>> {
+        labels.resolve(self)
+    }
+
+    /// Pair this hash with the labels to use when serializing it
+    pub fn with_labels<'a, L: LabelResolver + ?Sized>(&'a self, labels: &'a L) -> WithLabels<'a, Self, L> {
+        WithLabels::new(self, labels)
+    }
+}
+
+#[cfg(feature = "derive_serde")]
+mod serde_impls {
+    use super::Hash40;
+    use crate::labels::global::GlobalLabels;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+    use std::convert::TryFrom;
+    use std::fmt;
+
+    struct Hash40Visitor;
+
+    impl<'de> de::Visitor<'de> for Hash40Visitor {
+        type Value = Hash40;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a label, a hex string starting with 0x, or an integer")
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<Hash40, E> {
+            Ok(Hash40(value))
+        }
+
+        // formats like TOML only have signed integers
+        fn visit_i64<E: de::Error>(self, value: i64) -> Result<Hash40, E> {
+            u64::try_from(value)
+                .map(Hash40)
+                .map_err(|_| E::invalid_value(de::Unexpected::Signed(value), &self))
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<Hash40, E> {
+            value.parse()
+                .map_err(|_| E::custom(format!("{} is an invalid Hash40", value)))
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Hash40 {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_any(Hash40Visitor)
+        }
+    }
+
+    /// Serializes using the labels set by [`set_labels`](crate::set_labels)
+    impl Serialize for Hash40 {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            self.with_labels(&GlobalLabels).serialize(serializer)
+        }
+    }
+}
+
 // const crc32 implementation by leo60288
 
 macro_rules! reflect {
@@ -65,8 +251,25 @@ pub const fn crc32(bytes: &[u8]) -> u32 {
 }
 
 
-pub const fn hash40(string: &str) -> crate::Hash40 {
+pub const fn hash40(string: &str) -> Hash40 {
     let bytes = string.as_bytes();
 
-    ((bytes.len() as u64) << 32) + crc32(bytes) as u64
+    Hash40(((bytes.len() as u64) << 32) + crc32(bytes) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let hash = hash40("a01_smb_chijyou");
+
+        assert_eq!(hash.length(), 15);
+        assert_eq!("a01_smb_chijyou".parse(), Ok(hash));
+        assert_eq!(hash.to_string().parse(), Ok(hash));
+        assert_eq!(hash.as_u64().to_string().parse(), Ok(hash));
+        assert!("0xnothex".parse::<Hash40>().is_err());
+        assert_eq!("".parse::<Hash40>(), Err(ParseHash40Error::Empty));
+    }
 }
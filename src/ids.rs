@@ -0,0 +1,159 @@
+//! Finding `nus3bank_id`s and `tone_id`s that no entry uses, for adding new songs.
+
+use crate::edit::EditError;
+use crate::{hash40, Entry, SliFile};
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+/// The ids not used by any entry of a [`SliFile`], created by [`SliFile::free_ids`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FreeIds {
+    /// Unused `nus3bank_id`s in ascending order. The last range always ends at `u32::MAX` unless
+    /// that id is in use.
+    pub nus3bank_ids: Vec<RangeInclusive<u32>>,
+
+    /// Unused `tone_id`s, laid out like `nus3bank_ids`
+    pub tone_ids: Vec<RangeInclusive<u32>>,
+}
+
+/// Every range of ids missing from `used`, which must be sorted
+fn free_ranges<I: IntoIterator<Item = u32>>(used: I) -> Vec<RangeInclusive<u32>> {
+    let mut ranges = Vec::new();
+    let mut next = Some(0u32);
+    for id in used {
+        if let Some(start) = next.filter(|&start| start < id) {
+            ranges.push(start..=id - 1);
+        }
+        next = id.checked_add(1);
+    }
+
+    if let Some(start) = next {
+        ranges.push(start..=u32::MAX);
+    }
+
+    ranges
+}
+
+/// The id after the highest one in use, or 0 if none are. If `u32::MAX` is in use, the lowest
+/// free id instead.
+fn next_id(used: &BTreeSet<u32>) -> Option<u32> {
+    match used.iter().next_back() {
+        Some(highest) => highest.checked_add(1)
+            .or_else(|| free_ranges(used.iter().copied()).first().map(|range| *range.start())),
+        None => Some(0),
+    }
+}
+
+impl SliFile {
+    /// Find the `nus3bank_id`s and `tone_id`s no entry uses
+    pub fn free_ids(&self) -> FreeIds {
+        let nus3bank_ids: BTreeSet<_> = self.entries().iter().map(|entry| entry.nus3bank_id).collect();
+        let tone_ids: BTreeSet<_> = self.entries().iter().map(|entry| entry.tone_id).collect();
+
+        FreeIds {
+            nus3bank_ids: free_ranges(nus3bank_ids),
+            tone_ids: free_ranges(tone_ids),
+        }
+    }
+
+    /// Append an entry for each label with a `nus3bank_id` and `tone_id` no other entry uses,
+    /// returning the new entries.
+    ///
+    /// Ids are handed out counting up from the highest ones in use rather than filling gaps, so an
+    /// id freed by removing a song is never silently given to a different one. Only once `u32::MAX`
+    /// is in use are the gaps filled, starting from the lowest. Nothing is added
+    /// if any label already has an entry, or is given twice.
+    pub fn allocate_ids<S: AsRef<str>>(&mut self, labels: &[S]) -> Result<Vec<Entry>, EditError> {
+        let mut nus3bank_ids: BTreeSet<_> = self.entries().iter().map(|entry| entry.nus3bank_id).collect();
+        let mut tone_ids: BTreeSet<_> = self.entries().iter().map(|entry| entry.tone_id).collect();
+
+        let mut added: Vec<Entry> = Vec::with_capacity(labels.len());
+        for label in labels {
+            let tone_name = hash40(label.as_ref());
+            if self.contains(tone_name) || added.iter().any(|entry| entry.tone_name == tone_name) {
+                return Err(EditError::AlreadyExists(tone_name))
+            }
+
+            let (nus3bank_id, tone_id) = match (next_id(&nus3bank_ids), next_id(&tone_ids)) {
+                (Some(nus3bank_id), Some(tone_id)) => (nus3bank_id, tone_id),
+                _ => return Err(EditError::NoFreeIds),
+            };
+            nus3bank_ids.insert(nus3bank_id);
+            tone_ids.insert(tone_id);
+
+            added.push(Entry { tone_name, nus3bank_id, tone_id });
+        }
+
+        for entry in &added {
+            self.insert_or_replace(*entry);
+        }
+
+        Ok(added)
+    }
+}
+
+/// Writes ranges as `a-b`, `a` for a single id or `a-` for a range running to `u32::MAX`
+struct Ranges<'a>(&'a [RangeInclusive<u32>]);
+
+impl fmt::Display for Ranges<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, range) in self.0.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+
+            match (*range.start(), *range.end()) {
+                (start, end) if start == end => write!(f, "{}", start)?,
+                (start, u32::MAX) => write!(f, "{}-", start)?,
+                (start, end) => write!(f, "{}-{}", start, end)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for FreeIds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "nus3bank_id: {}", Ranges(&self.nus3bank_ids))?;
+        write!(f, "tone_id: {}", Ranges(&self.tone_ids))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_free_ids() {
+        let mut file = SliFile::new(1, vec![
+            Entry::new(hash40("a"), 1, 0),
+            Entry::new(hash40("b"), 2, 1),
+            Entry::new(hash40("c"), 5, 3),
+        ]);
+
+        let free = file.free_ids();
+        assert_eq!(free.nus3bank_ids, [0..=0, 3..=4, 6..=u32::MAX]);
+        assert_eq!(free.tone_ids, [2..=2, 4..=u32::MAX]);
+        assert_eq!(free.to_string(), "nus3bank_id: 0, 3-4, 6-\ntone_id: 2, 4-");
+
+        let added = file.allocate_ids(&["d", "e"]).unwrap();
+        assert_eq!(added, [Entry::new(hash40("d"), 6, 4), Entry::new(hash40("e"), 7, 5)]);
+        assert_eq!(file.get_by_label("e"), Some(&Entry::new(hash40("e"), 7, 5)));
+
+        assert_eq!(file.allocate_ids(&["f", "a"]), Err(EditError::AlreadyExists(hash40("a"))));
+        assert_eq!(file.allocate_ids(&["f", "f"]), Err(EditError::AlreadyExists(hash40("f"))));
+        assert!(!file.contains(hash40("f")));
+
+        let mut file = SliFile::new(1, vec![
+            Entry::new(hash40("a"), 0, 0),
+            Entry::new(hash40("b"), u32::MAX, 1),
+        ]);
+        assert_eq!(file.allocate_ids(&["c", "d"]).unwrap(), [
+            Entry::new(hash40("c"), 1, 2),
+            Entry::new(hash40("d"), 2, 3),
+        ]);
+    }
+}
@@ -0,0 +1,183 @@
+//! An annotated hex dump of a `.sli` file, for reverse engineering and debugging hand-patched
+//! files.
+//!
+//! Every field is shown next to the bytes it was read from, and anything [`SliFile::recover`] or
+//! [`SliFile::validate`] would complain about is flagged on the entries involved.
+//!
+//! [`SliFile::recover`]: crate::SliFile::recover
+//! [`SliFile::validate`]: crate::SliFile::validate
+
+use crate::recover::{Damage, Recovered};
+use crate::validate::{Diagnostic, Severity};
+use crate::view::{self, ENTRY_SIZE, HEADER_SIZE};
+use crate::{LabelResolver, Result, SliFile, WithLabels};
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// Wide enough for the hex of an entry, split into its three fields
+const HEX_WIDTH: usize = ENTRY_SIZE * 3 + 1;
+
+/// The parsed layout of a `.sli` file's bytes, created by [`inspect`]
+#[derive(Debug, Clone)]
+pub struct Inspection<'a> {
+    bytes: &'a [u8],
+    recovered: Recovered,
+    diagnostics: Vec<Diagnostic>,
+
+    /// Entries involved in any diagnostic
+    flagged: BTreeSet<usize>,
+}
+
+/// Inspect the bytes of a `.sli` file. Truncated files are inspected as far as they go, only a bad
+/// magic or a cut off header is an error.
+pub fn inspect(bytes: &[u8]) -> Result<Inspection<'_>> {
+    let recovered = SliFile::recover(bytes)?;
+    let diagnostics = recovered.file.validate();
+
+    let flagged = diagnostics.iter()
+        .flat_map(|diagnostic| match diagnostic {
+            Diagnostic::DuplicateToneName { indices, .. } => indices.clone(),
+            Diagnostic::ZeroLengthHash { index, .. }
+            | Diagnostic::ImplausibleHashLength { index, .. }
+            | Diagnostic::InvalidHash { index, .. } => vec![*index],
+            Diagnostic::SharedTone { tone_names, .. } => {
+                recovered.file.entries().iter()
+                    .enumerate()
+                    .filter(|(_, entry)| tone_names.contains(&entry.tone_name))
+                    .map(|(index, _)| index)
+                    .collect()
+            }
+            _ => Vec::new(),
+        })
+        .collect();
+
+    Ok(Inspection { bytes, recovered, diagnostics, flagged })
+}
+
+impl Inspection<'_> {
+    /// The entries which could be read, along with the version and any trailing bytes
+    pub fn file(&self) -> &SliFile {
+        &self.recovered.file
+    }
+
+    /// Where the data is cut off, if it is
+    pub fn damage(&self) -> &[Damage] {
+        &self.recovered.damage
+    }
+
+    /// The problems [`SliFile::validate`] found with the entries which could be read
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Whether the data is damaged or has any diagnostics
+    pub fn has_anomalies(&self) -> bool {
+        self.recovered.is_damaged() || !self.diagnostics.is_empty()
+    }
+
+    /// Pair the inspection with the labels to use for `tone_name`s when displaying it
+    pub fn with_labels<'a, L: LabelResolver + ?Sized>(&'a self, labels: &'a L) -> WithLabels<'a, Self, L> {
+        WithLabels::new(self, labels)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(" ")
+}
+
+fn write_line(f: &mut fmt::Formatter, offset: usize, hex: &str, annotation: fmt::Arguments) -> fmt::Result {
+    writeln!(f, "{:08x}  {:<width$}  {}", offset, hex, annotation, width = HEX_WIDTH)
+}
+
+impl<L: LabelResolver + ?Sized> fmt::Display for WithLabels<'_, Inspection<'_>, L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Inspection { bytes, recovered, diagnostics, flagged } = self.value;
+        let entries = recovered.file.entries();
+
+        let count = view::read_u32(bytes, 8);
+        let count_note = if recovered.is_damaged() {
+            format!(", but only {} are complete", entries.len())
+        } else {
+            String::new()
+        };
+
+        write_line(f, 0, &hex(&bytes[..4]), format_args!("  magic    {}", bytes[..4].escape_ascii()))?;
+        write_line(f, 4, &hex(&bytes[4..8]), format_args!("  version  {}", recovered.file.version()))?;
+        write_line(f, 8, &hex(&bytes[8..12]), format_args!("{} count    {}{}", mark(recovered.is_damaged()), count, count_note))?;
+
+        for (index, entry) in entries.iter().enumerate() {
+            let offset = HEADER_SIZE + index * ENTRY_SIZE;
+            let raw = &bytes[offset..offset + ENTRY_SIZE];
+            write_line(
+                f, offset,
+                &format!("{}  {}  {}", hex(&raw[..8]), hex(&raw[8..12]), hex(&raw[12..])),
+                format_args!(
+                    "{} entry {}  {} nus3bank_id={} tone_id={}",
+                    mark(flagged.contains(&index)), index,
+                    self.labels.resolve_or_hex(entry.tone_name), entry.nus3bank_id, entry.tone_id
+                ),
+            )?;
+        }
+
+        // either the cut off entry or the trailing bytes, never both
+        let end = HEADER_SIZE + entries.len() * ENTRY_SIZE;
+        let rest = &bytes[end..];
+        let label = if recovered.is_damaged() { "partial entry" } else { "trailing" };
+        for (i, chunk) in rest.chunks(ENTRY_SIZE).enumerate() {
+            let offset = end + i * ENTRY_SIZE;
+            if i == 0 {
+                write_line(f, offset, &hex(chunk), format_args!("! {}, {} bytes", label, rest.len()))?;
+            } else {
+                write_line(f, offset, &hex(chunk), format_args!(""))?;
+            }
+        }
+
+        if !recovered.damage.is_empty() || !diagnostics.is_empty() {
+            writeln!(f)?;
+        }
+        for damage in &recovered.damage {
+            writeln!(f, "{}: {}", Severity::Error, damage)?;
+        }
+        for diagnostic in diagnostics {
+            writeln!(f, "{}: {}", diagnostic.severity(), diagnostic.with_labels(self.labels))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn mark(flagged: bool) -> char {
+    if flagged { '!' } else { ' ' }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hash40, Entry};
+
+    #[test]
+    fn test_inspect() {
+        let a = Entry::new(hash40("a01_smb_chijyou"), 1, 0);
+        let b = Entry::new(hash40("a02_smb_chika"), 2, 1);
+        let bytes = SliFile::new(1, vec![a, b, a]).to_bytes();
+
+        let inspection = inspect(&bytes).unwrap();
+        let text = inspection.with_labels(&()).to_string();
+        assert!(text.starts_with("00000000  53 4c 49 00"));
+        assert!(text.contains(&format!(
+            "0000001c  {}  02 00 00 00  01 00 00 00  {}",
+            hex(&b.tone_name.as_u64().to_le_bytes()), "  entry 1"
+        )));
+        assert!(text.contains("! entry 2"));
+        assert!(text.contains("error: tone"));
+
+        let inspection = inspect(&bytes[..40]).unwrap();
+        assert_eq!(inspection.file().entries().len(), 1);
+        assert_eq!(inspection.damage().len(), 2);
+
+        let text = inspection.with_labels(&()).to_string();
+        assert!(text.contains("! count    3, but only 1 are complete"));
+        assert!(text.contains("! partial entry, 12 bytes"));
+    }
+}
@@ -0,0 +1,474 @@
+//! Resolving [`Hash40`]s back into the labels they were hashed from.
+//!
+//! ```rust,no_run
+//! # #[cfg(feature = "cli")]
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use sound_label_info::{SliFile, Labels};
+//!
+//! let file = SliFile::open("soundlabelinfo.sli")?;
+//! let labels = Labels::open("Hashes.txt")?;
+//!
+//! let yaml = serde_yaml::to_string(&file.with_labels(&labels))?;
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "cli"))]
+//! # fn main() {}
+//! ```
+
+use crate::{hash40, Hash40};
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::iter::FromIterator;
+use std::num::ParseIntError;
+use std::path::{Path, PathBuf};
+
+/// A lookup from a [`Hash40`] to the label it was hashed from
+pub trait LabelResolver {
+    /// Get the label for the given hash, if known
+    fn resolve(&self, hash: Hash40) -> Option<Cow<'_, str>>;
+
+    /// Get the label for the given hash, or its hex representation if unknown
+    fn resolve_or_hex(&self, hash: Hash40) -> Cow<'_, str> {
+        self.resolve(hash).unwrap_or_else(|| Cow::Owned(hash.to_string()))
+    }
+}
+
+impl<L: LabelResolver + ?Sized> LabelResolver for &L {
+    fn resolve(&self, hash: Hash40) -> Option<Cow<'_, str>> {
+        (**self).resolve(hash)
+    }
+}
+
+impl LabelResolver for HashMap<Hash40, String> {
+    fn resolve(&self, hash: Hash40) -> Option<Cow<'_, str>> {
+        self.get(&hash).map(|label| Cow::Borrowed(label.as_str()))
+    }
+}
+
+/// Resolves nothing, so every hash is written as hex
+impl LabelResolver for () {
+    fn resolve(&self, _: Hash40) -> Option<Cow<'_, str>> {
+        None
+    }
+}
+
+/// A set of known labels, keyed by their hash
+#[derive(Debug, Default, Clone)]
+pub struct Labels {
+    labels: HashMap<Hash40, String>,
+}
+
+impl Labels {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The BGM labels from `bgm_hashes.txt`, hashed at compile time
+    #[cfg(feature = "builtin_labels")]
+    pub fn builtin() -> Self {
+        let mut labels = Labels {
+            labels: HashMap::with_capacity(BUILTIN_LABELS.len()),
+        };
+        for &(hash, label) in BUILTIN_LABELS {
+            labels.insert_with_hash(hash, label);
+        }
+
+        labels
+    }
+
+    /// Load a label file, see [`Labels::parse`] for the accepted format
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, LabelsError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|error| LabelsError::Io { path: path.to_owned(), error })?;
+
+        Self::parse(&contents).map_err(|err| err.with_path(path))
+    }
+
+    /// Load several label files, where labels from later files take priority over earlier ones
+    pub fn open_all<I, P>(paths: I) -> Result<Self, LabelsError>
+        where I: IntoIterator<Item = P>,
+              P: AsRef<Path>,
+    {
+        let mut labels = Labels::new();
+        for path in paths {
+            labels.merge(Labels::open(path)?);
+        }
+
+        Ok(labels)
+    }
+
+    /// Parse a label list. Each line is either a plain label, which is hashed, or a
+    /// `0xHASH,label` pair as used by ParamLabels.csv. Blank lines and lines starting with `#`
+    /// are skipped, and both LF and CRLF line endings are accepted.
+    ///
+    /// Fails with every malformed line if any are found, see [`Labels::parse_lossy`] to skip them
+    /// instead.
+    pub fn parse(contents: &str) -> Result<Self, LabelsError> {
+        let (labels, malformed) = Self::parse_lossy(contents);
+        if malformed.is_empty() {
+            Ok(labels)
+        } else {
+            Err(LabelsError::Malformed { path: None, lines: malformed })
+        }
+    }
+
+    /// Parse a label list like [`Labels::parse`], returning the malformed lines alongside the
+    /// labels from every well-formed line
+    pub fn parse_lossy(contents: &str) -> (Self, Vec<MalformedLine>) {
+        let contents = contents.strip_prefix('\u{feff}').unwrap_or(contents);
+
+        let mut labels = Labels::new();
+        let mut malformed = Vec::new();
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+
+            match parse_line(line) {
+                Ok((Some(hash), label)) => labels.insert_with_hash(hash, label),
+                Ok((None, label)) => {
+                    labels.insert(label);
+                }
+                Err(reason) => malformed.push(MalformedLine {
+                    line: i + 1,
+                    contents: line.to_owned(),
+                    reason,
+                }),
+            }
+        }
+
+        (labels, malformed)
+    }
+
+    /// Add every label from `other`, replacing any existing label with the same hash
+    pub fn merge(&mut self, other: Labels) {
+        self.labels.extend(other.labels);
+    }
+
+    /// Add a label, returning its hash
+    pub fn insert<S: Into<String>>(&mut self, label: S) -> Hash40 {
+        let label = label.into();
+        let hash = hash40(&label);
+        self.labels.insert(hash, label);
+
+        hash
+    }
+
+    /// Add a label under a known hash, without rehashing it
+    pub fn insert_with_hash<S: Into<String>>(&mut self, hash: Hash40, label: S) {
+        self.labels.insert(hash, label.into());
+    }
+
+    pub fn get(&self, hash: Hash40) -> Option<&str> {
+        self.labels.get(&hash).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Hash40, &str)> {
+        self.labels.iter().map(|(hash, label)| (*hash, label.as_str()))
+    }
+}
+
+#[cfg(feature = "builtin_labels")]
+const BUILTIN_LABELS: &[(Hash40, &str)] = include!(concat!(env!("OUT_DIR"), "/builtin_labels.rs"));
+
+/// Parse a single non-blank, non-comment line into an optional explicit hash and its label
+fn parse_line(line: &str) -> Result<(Option<Hash40>, &str), MalformedReason> {
+    let (hash, label) = match line.find(',') {
+        Some(comma) => (&line[..comma], &line[comma + 1..]),
+        None => return Ok((None, line)),
+    };
+
+    let (hash, label) = (hash.trim(), label.trim());
+    if label.is_empty() {
+        return Err(MalformedReason::MissingLabel)
+    }
+    if label.contains(',') {
+        return Err(MalformedReason::TooManyFields)
+    }
+
+    let hex = hash.strip_prefix("0x")
+        .or_else(|| hash.strip_prefix("0X"))
+        .ok_or(MalformedReason::MissingHexPrefix)?;
+
+    u64::from_str_radix(hex, 16)
+        .map(|hash| (Some(Hash40(hash)), label))
+        .map_err(MalformedReason::InvalidHash)
+}
+
+/// A line of a label file which could not be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MalformedLine {
+    /// The line number, starting from 1
+    pub line: usize,
+    pub contents: String,
+    pub reason: MalformedReason,
+}
+
+impl fmt::Display for MalformedLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {} ({:?})", self.line, self.reason, self.contents)
+    }
+}
+
+/// Why a line of a label file could not be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MalformedReason {
+    /// The hash of a `0xHASH,label` pair did not start with `0x`
+    MissingHexPrefix,
+
+    /// The hash of a `0xHASH,label` pair was not valid hex
+    InvalidHash(ParseIntError),
+
+    /// A `0xHASH,label` pair had nothing after the comma
+    MissingLabel,
+
+    /// A line had more than the two fields of a `0xHASH,label` pair
+    TooManyFields,
+}
+
+impl fmt::Display for MalformedReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MalformedReason::MissingHexPrefix => write!(f, "hash must start with 0x"),
+            MalformedReason::InvalidHash(err) => write!(f, "invalid hash: {}", err),
+            MalformedReason::MissingLabel => write!(f, "missing label after comma"),
+            MalformedReason::TooManyFields => write!(f, "expected `0xHASH,label`"),
+        }
+    }
+}
+
+/// An error from loading labels
+#[derive(Debug)]
+pub enum LabelsError {
+    /// The label file could not be read
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+
+    /// One or more lines could not be parsed
+    Malformed {
+        path: Option<PathBuf>,
+        lines: Vec<MalformedLine>,
+    },
+}
+
+impl LabelsError {
+    fn with_path(self, path: &Path) -> Self {
+        match self {
+            LabelsError::Malformed { lines, .. } => {
+                LabelsError::Malformed { path: Some(path.to_owned()), lines }
+            }
+            err => err,
+        }
+    }
+}
+
+impl fmt::Display for LabelsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LabelsError::Io { path, error } => {
+                write!(f, "failed to read labels from {}: {}", path.display(), error)
+            }
+            LabelsError::Malformed { path, lines } => {
+                if let Some(path) = path {
+                    write!(f, "{}: ", path.display())?;
+                }
+                write!(f, "{} malformed label line(s)", lines.len())?;
+                for line in lines {
+                    write!(f, "\n  {}", line)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for LabelsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LabelsError::Io { error, .. } => Some(error),
+            LabelsError::Malformed { .. } => None,
+        }
+    }
+}
+
+impl From<LabelsError> for io::Error {
+    fn from(err: LabelsError) -> Self {
+        match err {
+            LabelsError::Io { error, .. } => error,
+            err => io::Error::new(io::ErrorKind::InvalidData, err.to_string()),
+        }
+    }
+}
+
+impl LabelResolver for Labels {
+    fn resolve(&self, hash: Hash40) -> Option<Cow<'_, str>> {
+        self.get(hash).map(Cow::Borrowed)
+    }
+}
+
+impl<S: Into<String>> Extend<S> for Labels {
+    fn extend<I: IntoIterator<Item = S>>(&mut self, iter: I) {
+        for label in iter {
+            self.insert(label);
+        }
+    }
+}
+
+impl<S: Into<String>> FromIterator<S> for Labels {
+    fn from_iter<I: IntoIterator<Item = S>>(iter: I) -> Self {
+        let mut labels = Labels::new();
+        labels.extend(iter);
+
+        labels
+    }
+}
+
+/// A value paired with the labels to use when serializing the hashes inside it.
+///
+/// Created by [`SliFile::with_labels`](crate::SliFile::with_labels) and friends.
+#[derive(Debug, Clone, Copy)]
+pub struct WithLabels<'a, T: ?Sized, L: ?Sized> {
+    pub value: &'a T,
+    pub labels: &'a L,
+}
+
+impl<'a, T: ?Sized, L: LabelResolver + ?Sized> WithLabels<'a, T, L> {
+    pub fn new(value: &'a T, labels: &'a L) -> Self {
+        WithLabels { value, labels }
+    }
+}
+
+#[cfg(feature = "derive_serde")]
+mod serde_impls {
+    use super::{LabelResolver, WithLabels};
+    use crate::{Entry, Hash40, SliFile};
+    use serde::ser::{Serialize, Serializer, SerializeSeq, SerializeStruct};
+
+    impl<L: LabelResolver + ?Sized> Serialize for WithLabels<'_, Hash40, L> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(&self.labels.resolve_or_hex(*self.value))
+        }
+    }
+
+    impl<L: LabelResolver + ?Sized> Serialize for WithLabels<'_, Entry, L> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut entry = serializer.serialize_struct("Entry", 3)?;
+            entry.serialize_field("tone_name", &WithLabels::new(&self.value.tone_name, self.labels))?;
+            entry.serialize_field("nus3bank_id", &self.value.nus3bank_id)?;
+            entry.serialize_field("tone_id", &self.value.tone_id)?;
+            entry.end()
+        }
+    }
+
+    impl<L: LabelResolver + ?Sized> Serialize for WithLabels<'_, [Entry], L> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut seq = serializer.serialize_seq(Some(self.value.len()))?;
+            for entry in self.value {
+                seq.serialize_element(&WithLabels::new(entry, self.labels))?;
+            }
+            seq.end()
+        }
+    }
+
+    impl<L: LabelResolver + ?Sized> Serialize for WithLabels<'_, SliFile, L> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let trailing = self.value.trailing();
+
+            let mut file = serializer.serialize_struct("SliFile", 2 + !trailing.is_empty() as usize)?;
+            file.serialize_field("version", &self.value.version())?;
+
+            // Before the entries, as TOML needs plain values ahead of the array of tables
+            if trailing.is_empty() {
+                file.skip_field("trailing")?;
+            } else {
+                let hex: String = trailing.iter().map(|byte| format!("{:02x}", byte)).collect();
+                file.serialize_field("trailing", &hex)?;
+            }
+
+            file.serialize_field("entries", &WithLabels::new(self.value.entries().as_slice(), self.labels))?;
+            file.end()
+        }
+    }
+}
+
+#[cfg(feature = "derive_serde")]
+pub(crate) mod global {
+    use super::{LabelResolver, Labels};
+    use crate::Hash40;
+
+    use std::borrow::Cow;
+    use std::sync::{Mutex, MutexGuard};
+
+    lazy_static::lazy_static! {
+        static ref LABELS: Mutex<Labels> = Mutex::new(Labels::new());
+    }
+
+    /// A poisoned lock only means another thread panicked mid-update, the labels are still usable
+    pub(crate) fn labels() -> MutexGuard<'static, Labels> {
+        LABELS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Resolves hashes using the labels set by [`set_labels`](crate::set_labels)
+    pub(crate) struct GlobalLabels;
+
+    impl LabelResolver for GlobalLabels {
+        fn resolve(&self, hash: Hash40) -> Option<Cow<'_, str>> {
+            labels().get(hash).map(|label| Cow::Owned(label.to_owned()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let contents = "\u{feff}# bgm labels\r\na01_smb_chijyou\r\n\r\n  0x10,custom_label  \r\nzz,bad\r\n0x1,\r\n";
+        let (labels, malformed) = Labels::parse_lossy(contents);
+
+        assert_eq!(labels.len(), 2);
+        assert_eq!(labels.get(hash40("a01_smb_chijyou")), Some("a01_smb_chijyou"));
+        assert_eq!(labels.get(Hash40(0x10)), Some("custom_label"));
+
+        let lines: Vec<_> = malformed.iter().map(|line| line.line).collect();
+        assert_eq!(lines, [5, 6]);
+        assert_eq!(malformed[1].reason, MalformedReason::MissingLabel);
+
+        assert!(Labels::parse(contents).is_err());
+    }
+
+    #[test]
+    fn test_merge_priority() {
+        let mut labels = Labels::parse("0x10,base\na01_smb_chijyou").unwrap();
+        labels.merge(Labels::parse("0x10,override").unwrap());
+
+        assert_eq!(labels.get(Hash40(0x10)), Some("override"));
+        assert_eq!(labels.len(), 2);
+    }
+
+    #[cfg(feature = "builtin_labels")]
+    #[test]
+    fn test_builtin() {
+        let labels = Labels::builtin();
+
+        assert_eq!(labels.get(hash40("a01_smb_chijyou")), Some("a01_smb_chijyou"));
+        assert_eq!(labels.get(hash40("zz04_f_pickel")), Some("zz04_f_pickel"));
+    }
+}
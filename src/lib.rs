@@ -1,44 +1,60 @@
 //! A Rust library for working with `soundlabelinfo.sli` files from Smash Ultimate. This allows for
 //! modifying various properties associated with  background music.
 //! 
-/// ```rust
-/// # fn main() -> binread::BinResult<()> {
-/// use sound_label_info::SliFile;
-/// 
-/// let mut file = SliFile::open("soundlabelinfo.sli")?;
-/// 
-/// for entry in file.entries() {
-///     println!("tone_name: {:#X}", entry.tone_name);
-/// }
-/// 
-/// for entry in file.entries_mut() {
-///     entry.tone_id = 0;
-/// }
-/// 
-/// file.save("soundlabelinfo_out.sli")?;
-/// # Ok(())
-/// # }
-/// ```
+//! ```rust,no_run
+//! # fn main() -> sound_label_info::Result<()> {
+//! use sound_label_info::SliFile;
+//! 
+//! let mut file = SliFile::open("soundlabelinfo.sli")?;
+//! 
+//! for entry in file.entries() {
+//!     println!("tone_name: {:#X}", entry.tone_name);
+//! }
+//! 
+//! for entry in file.entries_mut() {
+//!     entry.tone_id = 0;
+//! }
+//! 
+//! file.save("soundlabelinfo_out.sli")?;
+//! # Ok(())
+//! # }
+//! ```
 
-use binread::{BinRead, BinReaderExt, derive_binread};
+use binread::{BinRead, ReadOptions};
 use binwrite::{BinWrite, WriterOption};
 
-use std::fs::File;
-use std::path::Path;
-use std::io::{self, Write, BufReader, BufWriter};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write, BufReader, BufWriter};
 
 #[cfg(feature = "derive_serde")]
 use serde::{Serialize, Deserialize};
 
-mod hash40;
-
-/// Type alias for Hash40
-pub type Hash40 = u64;
-
-pub use binread::{BinResult as Result, Error};
-
-/// ```rust
-/// # fn main() -> binread::BinResult<()> {
+pub mod detect;
+pub mod diff;
+pub mod edit;
+pub mod error;
+pub mod hash40;
+pub mod ids;
+pub mod inspect;
+pub mod labels;
+pub mod merge;
+pub mod recover;
+pub mod validate;
+pub mod view;
+
+pub use hash40::{Hash40, hash40};
+pub use labels::{Labels, LabelResolver, LabelsError, WithLabels};
+
+pub use error::{Error, Result};
+
+/// ```rust,no_run
+/// # fn main() -> sound_label_info::Result<()> {
 /// use sound_label_info::SliFile;
 /// 
 /// let mut file = SliFile::open("soundlabelinfo.sli")?;
@@ -55,125 +71,481 @@ pub use binread::{BinResult as Result, Error};
 /// # Ok(())
 /// # }
 /// ```
-#[derive_binread]
-#[cfg_attr(feature = "derive_serde", derive(Serialize, Deserialize))]
-#[derive(Debug)]
-#[br(magic = b"SLI\x00")]
-pub struct SliFile (
-    u32,
-    
-    #[br(temp)]
-    u32,
-
-    #[br(count = self_1)]
-    Vec<Entry>,
-);
+#[cfg_attr(feature = "derive_serde", derive(Deserialize))]
+#[cfg_attr(feature = "derive_serde", serde(from = "SliFileRepr"))]
+#[derive(Debug, Clone)]
+pub struct SliFile {
+    /// The header version, see [`KNOWN_VERSIONS`]
+    version: u32,
+
+    entries: Vec<Entry>,
+
+    /// Anything after the last entry, kept so saving never drops data a newer game version added
+    trailing: Vec<u8>,
+
+    index: EntryIndex,
+}
+
+/// Header versions seen in `soundlabelinfo.sli` files shipped with the game
+pub const KNOWN_VERSIONS: &[u32] = &[1];
+
+/// Bounds on what the header of a file being read may claim, checked before anything is
+/// allocated for the entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadLimits {
+    pub max_entries: u32,
+
+    /// Fail with [`Error::UnknownVersion`] rather than reading a file whose version is not in
+    /// [`KNOWN_VERSIONS`]. Otherwise such files are read, and reported by [`SliFile::validate`].
+    pub reject_unknown_versions: bool,
+}
+
+impl ReadLimits {
+    /// Far more entries than the game's file has, while still only allocating 16 MiB
+    pub const DEFAULT_MAX_ENTRIES: u32 = 1 << 20;
+}
+
+impl Default for ReadLimits {
+    fn default() -> Self {
+        ReadLimits {
+            max_entries: Self::DEFAULT_MAX_ENTRIES,
+            reject_unknown_versions: false,
+        }
+    }
+}
+
+/// The serialized shape of an [`SliFile`], used so deserialized files get their index built.
+///
+/// Serde derives also accept structs written as sequences, so the older `[version, entries]`
+/// form still deserializes, as do files written before `trailing` was added.
+#[cfg(feature = "derive_serde")]
+#[derive(Deserialize)]
+struct SliFileRepr {
+    version: u32,
+    entries: Vec<Entry>,
+
+    #[serde(default, deserialize_with = "deserialize_hex")]
+    trailing: Vec<u8>,
+}
+
+#[cfg(feature = "derive_serde")]
+impl From<SliFileRepr> for SliFile {
+    fn from(SliFileRepr { version, entries, trailing }: SliFileRepr) -> Self {
+        SliFile { trailing, ..SliFile::new(version, entries) }
+    }
+}
+
+/// Parse bytes written as pairs of hex digits, ignoring whitespace between them
+#[cfg(feature = "derive_serde")]
+fn deserialize_hex<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Vec<u8>, D::Error> {
+    use serde::de::Error as _;
+
+    let hex: String = String::deserialize(deserializer)?.split_whitespace().collect();
+    let invalid = || D::Error::custom(format!("trailing bytes must be pairs of hex digits, found {:?}", hex));
+    if !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid())
+    }
+
+    hex.as_bytes().chunks(2)
+        .map(|pair| match pair {
+            [high, low] => Ok((hex_digit(*high) << 4) | hex_digit(*low)),
+            _ => Err(invalid()),
+        })
+        .collect()
+}
+
+#[cfg(feature = "derive_serde")]
+fn hex_digit(c: u8) -> u8 {
+    (c as char).to_digit(16).unwrap() as u8
+}
+
+/// Serializes using the labels set by [`set_labels`], see [`SliFile::with_labels`] to pick the
+/// labels explicitly
+#[cfg(feature = "derive_serde")]
+impl Serialize for SliFile {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.with_labels(&labels::global::GlobalLabels).serialize(serializer)
+    }
+}
+
+/// Create a new file next to `path` to save into, named uniquely so concurrent saves of the same
+/// path never write into each other's temporary file
+fn create_temp_file(path: &Path) -> io::Result<(File, PathBuf)> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let name = path.file_name().unwrap_or_else(|| "soundlabelinfo.sli".as_ref());
+    loop {
+        let mut temp_name = OsString::from(".");
+        temp_name.push(name);
+        temp_name.push(format!(".{}.{}.tmp", process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+        let temp_path = path.with_file_name(temp_name);
+
+        // left behind by a crashed process which had the same pid, try the next name
+        match OpenOptions::new().write(true).create_new(true).open(&temp_path) {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            result => return result.map(|file| (file, temp_path)),
+        }
+    }
+}
+
+/// Maps each `tone_name` to the position of its first entry.
+///
+/// Handing out all entries mutably marks the index stale, after which shared lookups fall back to
+/// a linear scan until the next mutable lookup rebuilds it. Handing out a single entry only
+/// remembers which one, so just that entry is re-checked.
+#[derive(Debug, Default, Clone)]
+struct EntryIndex {
+    positions: HashMap<Hash40, usize>,
+    stale: bool,
+
+    /// The position and `tone_name` of the entry last handed out by [`SliFile::get_mut`]
+    dirty: Option<(usize, Hash40)>,
+}
+
+impl EntryIndex {
+    fn new(entries: &[Entry]) -> Self {
+        let mut positions = HashMap::with_capacity(entries.len());
+        for (i, entry) in entries.iter().enumerate() {
+            positions.entry(entry.tone_name).or_insert(i);
+        }
+
+        EntryIndex { positions, stale: false, dirty: None }
+    }
+
+    fn position(&self, entries: &[Entry], tone_name: Hash40) -> Option<usize> {
+        if self.stale || self.is_renamed(entries, tone_name) {
+            entries.iter().position(|entry| entry.tone_name == tone_name)
+        } else {
+            self.positions.get(&tone_name).copied()
+        }
+    }
+
+    /// Whether the dirty entry was renamed from or to `tone_name`, so its position is out of date
+    fn is_renamed(&self, entries: &[Entry], tone_name: Hash40) -> bool {
+        match self.dirty {
+            Some((i, old)) => {
+                let new = entries[i].tone_name;
+                new != old && (tone_name == old || tone_name == new)
+            }
+            None => false,
+        }
+    }
+
+    /// Bring the index up to date, rebuilding it only if it is stale
+    fn refresh(&mut self, entries: &[Entry]) {
+        if self.stale {
+            *self = EntryIndex::new(entries);
+            return
+        }
+
+        if let Some((i, old)) = self.dirty.take() {
+            let new = entries[i].tone_name;
+            if new == old {
+                return
+            }
+
+            match entries.iter().position(|entry| entry.tone_name == old) {
+                Some(position) => self.positions.insert(old, position),
+                None => self.positions.remove(&old),
+            };
+            match self.positions.get(&new) {
+                Some(&position) if position < i => (),
+                _ => {
+                    self.positions.insert(new, i);
+                }
+            }
+        }
+    }
+}
+
+/// Always reads little endian, ignoring the endianness in `options`
+impl BinRead for SliFile {
+    type Args = ();
+
+    fn read_options<R: Read + Seek>(reader: &mut R, _: &ReadOptions, _: ()) -> binread::BinResult<Self> {
+        let pos = reader.stream_position()?;
+
+        SliFile::read(reader).map_err(|err| match err {
+            Error::Io { error, .. } => binread::Error::Io(error),
+            Error::BadMagic { found } => binread::Error::BadMagic { pos, found: Box::new(found) },
+            err => binread::Error::Custom { pos, err: Box::new(err) },
+        })
+    }
+}
+
+/// Fill `buf`, reporting running out of data as the header or entry at `offset` being truncated
+fn read_exact_or_truncated<R: Read>(reader: &mut R, buf: &mut [u8], entry_index: Option<usize>, offset: u64) -> Result<()> {
+    reader.read_exact(buf).map_err(|error| match error.kind() {
+        io::ErrorKind::UnexpectedEof => Error::Truncated { entry_index, offset },
+        _ => Error::from(error),
+    })
+}
 
 impl BinWrite for SliFile {
     fn write_options<W: Write>(&self, writer: &mut W, options: &WriterOption) -> io::Result<()> {
         (
             "SLI\x00",
-            self.0,
-            self.1.len() as u32,
-            &self.1
-        ).write_options(writer, options)
+            self.version,
+            self.entries.len() as u32,
+            &self.entries
+        ).write_options(writer, options)?;
+
+        writer.write_all(&self.trailing)
     }
 }
 
 /// An entry representing a single tone
 #[cfg_attr(feature = "derive_serde", derive(Serialize, Deserialize))]
-#[derive(BinRead, BinWrite, Debug)]
+#[derive(BinRead, BinWrite, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Entry {
-    #[serde(with = "serde_hash40")]
     pub tone_name: Hash40,
     pub nus3bank_id: u32,
     pub tone_id: u32,
 }
 
+impl Entry {
+    pub fn new(tone_name: Hash40, nus3bank_id: u32, tone_id: u32) -> Self {
+        Entry { tone_name, nus3bank_id, tone_id }
+    }
+}
+
+/// Set the labels used when serializing without explicit labels.
+///
+/// This is shared by the whole process, prefer [`SliFile::with_labels`] where possible. Unlike
+/// [`Labels::open`] no line is rejected, lines which aren't a well-formed `hash,label` pair are
+/// used as plain labels, the same as before the `hash,label` form was supported.
 #[cfg(feature = "derive_serde")]
 pub fn set_labels<P: AsRef<Path>>(path: P) -> Result<()> {
-    fn inner(path: &Path) -> Result<()> {
-        let contents = std::fs::read_to_string(path)?;
-        let labels = contents.split("\n")
-            .map(|string| (hash40::hash40(string.trim()), string.to_owned()))
-            .collect();
-
-        *serde_hash40::LABELS.lock().unwrap() = labels;
+    let path = path.as_ref();
+    let contents = fs::read_to_string(path)
+        .map_err(|error| LabelsError::Io { path: path.to_owned(), error })?;
 
-        Ok(())
+    let (mut labels, malformed) = Labels::parse_lossy(&contents);
+    for line in malformed {
+        labels.insert(line.contents);
     }
+    *labels::global::labels() = labels;
 
-    inner(path.as_ref())
+    Ok(())
 }
 
-#[cfg(feature = "derive_serde")]
-mod serde_hash40 {
-    use std::{
-        sync::Mutex,
-        collections::HashMap,
-    };
+impl SliFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut reader = BufReader::new(File::open(path).map_err(|err| Error::from(err).at_path(path))?);
 
-    lazy_static::lazy_static! {
-        pub static ref LABELS: Mutex<HashMap<Hash40, String>> = Mutex::new(HashMap::new());
+        Self::read(&mut reader).map_err(|err| err.at_path(path))
     }
 
-    use super::{hash40::hash40, Hash40};
-    use serde::{Deserialize, Deserializer, Serializer};
+    /// Read a file starting at the current position of `reader`
+    pub fn read<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        Self::read_with_limits(reader, &ReadLimits::default())
+    }
 
-    pub fn deserialize<'de, D, E>(deserializer: D) -> Result<u64, D::Error>
-    where
-        D: Deserializer<'de, Error = E>,
-        E: serde::de::Error,
-    {
-        let s: String = Deserialize::deserialize(deserializer)?;
+    /// Read a file starting at the current position of `reader`, rejecting it if its header
+    /// claims more entries than `limits` allow or the rest of `reader` can hold
+    pub fn read_with_limits<R: Read + Seek>(reader: &mut R, limits: &ReadLimits) -> Result<Self> {
+        let start = reader.stream_position()?;
 
-        if s.starts_with("0x") {
-            u64::from_str_radix(s.trim_start_matches("0x"), 16)
-                .map_err(|_| D::Error::custom(format!("{} is an invalid Hash40", s)))
-        } else {
-            Ok(hash40(&s))
+        let mut magic = [0; 4];
+        read_exact_or_truncated(reader, &mut magic, None, start)?;
+        if magic != *detect::SLI_MAGIC {
+            return Err(Error::BadMagic { found: magic })
         }
-    }
 
-    pub fn serialize<S>(hash40: &Hash40, serializer: S) -> Result<S::Ok, S::Error> 
-        where S: Serializer,
-    {
-        match LABELS.lock().unwrap().get(hash40) {
-            Some(label) => {
-                serializer.serialize_str(&label)
-            }
-            None => {
-                serializer.serialize_str(&format!("{:#x}", hash40))
-            }
+        let mut header = [0; view::HEADER_SIZE - 4];
+        read_exact_or_truncated(reader, &mut header, None, start)?;
+        let version = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let count = u32::from_le_bytes(header[4..].try_into().unwrap());
+
+        if limits.reject_unknown_versions && !KNOWN_VERSIONS.contains(&version) {
+            return Err(Error::UnknownVersion { version })
         }
+
+        if count > limits.max_entries {
+            return Err(Error::TooManyEntries { count, max: limits.max_entries })
+        }
+
+        let entries_start = reader.stream_position()?;
+        let available = reader.seek(SeekFrom::End(0))?.saturating_sub(entries_start) / view::ENTRY_SIZE as u64;
+        reader.seek(SeekFrom::Start(entries_start))?;
+        if u64::from(count) > available {
+            let offset = entries_start + available * view::ENTRY_SIZE as u64;
+            return Err(Error::CountMismatch { count, available, offset })
+        }
+
+        let mut entries = Vec::with_capacity(count as usize);
+        let mut bytes = [0; view::ENTRY_SIZE];
+        for i in 0..count as usize {
+            let offset = entries_start + (i * view::ENTRY_SIZE) as u64;
+            read_exact_or_truncated(reader, &mut bytes, Some(i), offset)?;
+            entries.push(view::read_entry(&bytes));
+        }
+
+        let mut trailing = Vec::new();
+        reader.read_to_end(&mut trailing)?;
+
+        Ok(SliFile { trailing, ..SliFile::new(version, entries) })
     }
-}
 
+    /// Read a file from any reader, such as stdin, by buffering it in memory since parsing needs
+    /// to seek
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
 
-impl SliFile {
-    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        BufReader::new(File::open(path)?).read_le()
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::read(&mut Cursor::new(bytes))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(view::HEADER_SIZE + self.entries.len() * view::ENTRY_SIZE + self.trailing.len());
+        self.write(&mut bytes).expect("writing to a Vec never fails");
+
+        bytes
     }
 
     pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
-        self.write(&mut BufWriter::new(File::create(path)?))
+        let path = path.as_ref();
+        let result = File::create(path)
+            .map_err(Error::from)
+            .and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                self.write(&mut writer)?;
+                writer.flush().map_err(Error::from)
+            });
+
+        result.map_err(|err| err.at_path(path))
+    }
+
+    /// Save by writing to a temporary file next to `path` and renaming it over `path`, so a
+    /// failed write never leaves a partially written file behind. The permissions of an existing
+    /// file at `path` are kept.
+    pub fn save_atomic<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let (file, temp_path) = create_temp_file(path).map_err(|err| Error::from(err).at_path(path))?;
+
+        let result = (|| -> Result<()> {
+            if let Ok(metadata) = fs::metadata(path) {
+                file.set_permissions(metadata.permissions())?;
+            }
+
+            let mut writer = BufWriter::new(file);
+            self.write(&mut writer)?;
+            writer.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+            fs::rename(&temp_path, path)?;
+            Ok(())
+        })();
+
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+
+        result.map_err(|err| err.at_path(path))
     }
 
     pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
         self.write_options(writer, &binwrite::writer_option_new!(endian: binwrite::Endian::Little))
-            .map_err(Into::into)
+            .map_err(Error::from)
     }
 
     pub fn new(version: u32, entries: Vec<Entry>) -> Self {
-        SliFile(version, entries)
+        let index = EntryIndex::new(&entries);
+        SliFile { version, entries, trailing: Vec::new(), index }
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn set_version(&mut self, version: u32) {
+        self.version = version;
+    }
+
+    /// The bytes after the last entry, which the game's file doesn't have
+    pub fn trailing(&self) -> &[u8] {
+        &self.trailing
+    }
+
+    pub fn set_trailing(&mut self, trailing: Vec<u8>) {
+        self.trailing = trailing;
+    }
+
+    /// Whether the header version is one of [`KNOWN_VERSIONS`]
+    pub fn has_known_version(&self) -> bool {
+        KNOWN_VERSIONS.contains(&self.version)
     }
 
     pub fn entries(&self) -> &Vec<Entry> {
-        &self.1
+        &self.entries
     }
 
+    /// Mutable access to the raw entries. Lookups by `tone_name` stay correct afterwards, but
+    /// shared lookups are linear until the next `&mut self` lookup rebuilds the index.
     pub fn entries_mut(&mut self) -> &mut Vec<Entry> {
-        &mut self.1
+        self.index.stale = true;
+        &mut self.entries
+    }
+
+    fn reindex(&mut self) {
+        self.index.refresh(&self.entries);
+    }
+
+    /// Pair this file with the labels to use for `tone_name`s when serializing
+    pub fn with_labels<'a, L: LabelResolver + ?Sized>(&'a self, labels: &'a L) -> WithLabels<'a, Self, L> {
+        WithLabels::new(self, labels)
+    }
+
+    /// Get the entry for the given `tone_name` hash
+    pub fn get(&self, tone_name: Hash40) -> Option<&Entry> {
+        self.index.position(&self.entries, tone_name).map(|i| &self.entries[i])
+    }
+
+    /// Get the entry for the given `tone_name` label, such as `"a01_smb_chijyou"`
+    pub fn get_by_label(&self, label: &str) -> Option<&Entry> {
+        self.get(hash40(label))
+    }
+
+    /// Get a mutable reference to the entry for the given `tone_name` hash
+    pub fn get_mut(&mut self, tone_name: Hash40) -> Option<&mut Entry> {
+        self.reindex();
+        let i = self.index.position(&self.entries, tone_name)?;
+
+        // the caller may change tone_name through the reference
+        self.index.dirty = Some((i, tone_name));
+        Some(&mut self.entries[i])
+    }
+
+    /// Check whether an entry exists for the given `tone_name` hash
+    pub fn contains(&self, tone_name: Hash40) -> bool {
+        self.get(tone_name).is_some()
+    }
+
+    /// Replace the entry sharing `entry.tone_name` in place, returning the previous entry, or
+    /// append it to the end of the file if no such entry exists.
+    pub fn insert_or_replace(&mut self, entry: Entry) -> Option<Entry> {
+        self.reindex();
+        match self.index.position(&self.entries, entry.tone_name) {
+            Some(i) => Some(std::mem::replace(&mut self.entries[i], entry)),
+            None => {
+                self.index.positions.insert(entry.tone_name, self.entries.len());
+                self.entries.push(entry);
+                None
+            }
+        }
+    }
+
+    /// Remove the entry for the given `tone_name` hash, keeping the order of the remaining entries
+    pub fn remove(&mut self, tone_name: Hash40) -> Option<Entry> {
+        self.reindex();
+        let i = self.index.position(&self.entries, tone_name)?;
+        let entry = self.entries.remove(i);
+        self.index = EntryIndex::new(&self.entries);
+
+        Some(entry)
     }
 }
 
@@ -194,4 +566,159 @@ mod tests {
         assert_eq!(original, round_trip);
         //sound_label_info.save("sound_label_info_out.bin").unwrap();
     }
+
+    #[test]
+    fn test_bytes() {
+        let file = SliFile::new(1, vec![
+            Entry::new(hash40("a01_smb_chijyou"), 1, 0),
+            Entry::new(hash40("a02_smb_chika"), 2, 1),
+        ]);
+
+        let bytes = file.to_bytes();
+        assert_eq!(&bytes[..12], b"SLI\x00\x01\x00\x00\x00\x02\x00\x00\x00");
+        assert_eq!(bytes.len(), 12 + 2 * 16);
+
+        let read = SliFile::from_bytes(&bytes).unwrap();
+        assert_eq!(read.version(), 1);
+        assert_eq!(read.entries(), file.entries());
+
+        assert!(matches!(SliFile::from_bytes(&bytes[..20]), Err(Error::CountMismatch { count: 2, available: 0, offset: 12 })));
+        assert!(matches!(SliFile::from_bytes(&bytes[..6]), Err(Error::Truncated { entry_index: None, offset: 0 })));
+        assert!(matches!(SliFile::from_bytes(b"SLJ\x00"), Err(Error::BadMagic { found: [b'S', b'L', b'J', 0] })));
+    }
+
+    #[test]
+    fn test_limits() {
+        let hostile = b"SLI\x00\x01\x00\x00\x00\xff\xff\xff\xff";
+        let err = SliFile::from_bytes(hostile).unwrap_err();
+        assert!(matches!(err, Error::TooManyEntries { count: u32::MAX, max: ReadLimits::DEFAULT_MAX_ENTRIES }));
+
+        let limits = ReadLimits { max_entries: u32::MAX, ..Default::default() };
+        let err = SliFile::read_with_limits(&mut Cursor::new(hostile), &limits).unwrap_err();
+        assert!(matches!(err, Error::CountMismatch { count: u32::MAX, available: 0, offset: 12 }));
+
+        let file = SliFile::new(1, vec![Entry::new(hash40("a01_smb_chijyou"), 1, 0); 3]);
+        let limits = ReadLimits { max_entries: 2, ..Default::default() };
+        assert!(SliFile::read_with_limits(&mut Cursor::new(file.to_bytes()), &limits).is_err());
+
+        let mut file = SliFile::new(2, Vec::new());
+        let limits = ReadLimits { reject_unknown_versions: true, ..Default::default() };
+        let err = SliFile::read_with_limits(&mut Cursor::new(file.to_bytes()), &limits).unwrap_err();
+        assert!(matches!(err, Error::UnknownVersion { version: 2 }));
+
+        file.set_version(1);
+        assert!(SliFile::read_with_limits(&mut Cursor::new(file.to_bytes()), &limits).is_ok());
+    }
+
+    #[test]
+    fn test_trailing() {
+        let mut bytes = SliFile::new(1, vec![Entry::new(hash40("a01_smb_chijyou"), 1, 0)]).to_bytes();
+        bytes.extend_from_slice(b"\x01\x02\x03");
+
+        let file = SliFile::from_bytes(&bytes).unwrap();
+        assert_eq!(file.entries().len(), 1);
+        assert_eq!(file.trailing(), b"\x01\x02\x03");
+        assert_eq!(file.to_bytes(), bytes);
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn test_positional_repr() {
+        let expected = [Entry::new(hash40("a01_smb_chijyou"), 1, 0)];
+
+        let yaml = "---\n- 1\n- - tone_name: a01_smb_chijyou\n    nus3bank_id: 1\n    tone_id: 0\n";
+        let file: SliFile = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!((file.version(), file.entries().as_slice()), (1, &expected[..]));
+        assert!(file.trailing().is_empty());
+
+        let json = r#"[1, [{"tone_name": "a01_smb_chijyou", "nus3bank_id": 1, "tone_id": 0}]]"#;
+        let file: SliFile = serde_json::from_str(json).unwrap();
+        assert_eq!((file.version(), file.entries().as_slice()), (1, &expected[..]));
+        assert_eq!(file.get(hash40("a01_smb_chijyou")), Some(&expected[0]));
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn test_trailing_text_formats() {
+        let mut file = SliFile::new(1, vec![Entry::new(hash40("a01_smb_chijyou"), 1, 0)]);
+        file.set_trailing(vec![0x00, 0xab, 0xff]);
+        let labels = Labels::new();
+        let bytes = file.to_bytes();
+
+        let yaml = serde_yaml::to_string(&file.with_labels(&labels)).unwrap();
+        assert!(yaml.contains("trailing: 00abff"));
+        assert_eq!(serde_yaml::from_str::<SliFile>(&yaml).unwrap().to_bytes(), bytes);
+
+        let toml = toml::to_string(&file.with_labels(&labels)).unwrap();
+        assert_eq!(toml::from_str::<SliFile>(&toml).unwrap().to_bytes(), bytes);
+
+        let json = serde_json::to_string(&file.with_labels(&labels)).unwrap();
+        assert_eq!(serde_json::from_str::<SliFile>(&json).unwrap().to_bytes(), bytes);
+
+        let spaced = r#"{"version": 1, "entries": [], "trailing": "00 ab\nff"}"#;
+        assert_eq!(serde_json::from_str::<SliFile>(spaced).unwrap().trailing(), [0x00, 0xab, 0xff]);
+
+        for trailing in &["abc", "zz", "+f", "é0"] {
+            let json = format!(r#"{{"version": 1, "entries": [], "trailing": "{}"}}"#, trailing);
+            assert!(serde_json::from_str::<SliFile>(&json).is_err(), "{:?} was accepted", trailing);
+        }
+    }
+
+    #[test]
+    fn test_save_atomic() {
+        let dir = std::env::temp_dir().join(format!("sli_save_atomic_{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("soundlabelinfo.sli");
+        fs::write(&path, b"old").unwrap();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        }
+
+        let file = SliFile::new(1, vec![Entry::new(hash40("a01_smb_chijyou"), 1, 0)]);
+        file.save_atomic(&path).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), file.to_bytes());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_index() {
+        let mut file = SliFile::new(0, vec![
+            Entry::new(hash40("a01_smb_chijyou"), 1, 0),
+            Entry::new(hash40("a02_smb_chika"), 2, 1),
+            Entry::new(hash40("a03_smb_suichu"), 3, 2),
+        ]);
+
+        assert_eq!(file.get_by_label("a02_smb_chika").unwrap().nus3bank_id, 2);
+        assert!(file.insert_or_replace(Entry::new(hash40("a02_smb_chika"), 5, 5)).is_some());
+        assert!(file.insert_or_replace(Entry::new(hash40("a04_lnd_chika"), 4, 3)).is_none());
+        assert_eq!(file.remove(hash40("a01_smb_chijyou")).unwrap().nus3bank_id, 1);
+        assert!(!file.contains(hash40("a01_smb_chijyou")));
+        assert_eq!(file.get_by_label("a03_smb_suichu").unwrap().tone_id, 2);
+
+        file.get_mut(hash40("a03_smb_suichu")).unwrap().tone_name = hash40("a02_smb_chika");
+        assert_eq!(file.get_by_label("a02_smb_chika").unwrap().nus3bank_id, 5);
+        assert!(!file.contains(hash40("a03_smb_suichu")));
+        file.get_mut(hash40("a02_smb_chika")).unwrap().tone_name = hash40("a05_smb3_hikousen");
+        assert_eq!(file.get_by_label("a02_smb_chika").unwrap().nus3bank_id, 3);
+        file.get_mut(hash40("a02_smb_chika")).unwrap().tone_name = hash40("a03_smb_suichu");
+        assert_eq!(file.get_by_label("a03_smb_suichu").unwrap().tone_id, 2);
+
+        file.entries_mut()[0].tone_name = hash40("renamed");
+        assert!(file.get_by_label("renamed").is_some());
+        assert!(file.get_mut(hash40("a02_smb_chika")).is_none());
+
+        let order: Vec<_> = file.entries().iter().map(|entry| entry.nus3bank_id).collect();
+        assert_eq!(order, [5, 3, 4]);
+    }
 }
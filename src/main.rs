@@ -1,40 +1,1008 @@
-use sound_label_info::SliFile;
+use sound_label_info::{Entry, Error, Hash40, ReadLimits, SliFile, Labels, LabelResolver, WithLabels};
+use sound_label_info::detect::{self, Format};
+use sound_label_info::hash40::ParseHash40Error;
+use sound_label_info::inspect;
+use sound_label_info::merge::MergePolicy;
 use structopt::StructOpt;
+use structopt::clap::ErrorKind;
 
+use std::borrow::Cow;
 use std::path::{Path, PathBuf};
+use std::io::{self, Cursor, Read, Write};
 use std::fs;
+use std::process;
+use std::str::FromStr;
+
+/// The command ran, but found a problem such as validation errors, merge conflicts or a missing tone
+const EXIT_FAILURE: i32 = 1;
+
+/// The command could not run, such as from unreadable or malformed input
+const EXIT_ERROR: i32 = 2;
 
 #[derive(StructOpt)]
+#[structopt(after_help = "Any file path can be - to read from stdin or write to stdout.\n\n\
+                          Exits with 1 if the command found a problem and 2 if it could not run.")]
 struct Args {
-    in_file: PathBuf,
-    out_file: PathBuf,
+    #[structopt(subcommand)]
+    command: Command,
 
-    #[structopt(short, long)]
+    /// Labels file to layer over the built-in labels [default: Hashes.txt if present]
+    #[structopt(short, long, global = true)]
     labels: Option<PathBuf>,
+
+    /// Refuse to read .sli files with an unknown header version instead of warning about them
+    #[structopt(long, global = true)]
+    strict_version: bool,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Convert a .sli file to yaml
+    ToYaml {
+        input: PathBuf,
+        output: PathBuf,
+    },
+
+    /// Convert a yaml file to .sli
+    ToSli {
+        input: PathBuf,
+        output: PathBuf,
+    },
+
+    /// Convert a .sli file to yaml, json, toml or csv
+    Export {
+        input: PathBuf,
+        output: PathBuf,
+
+        /// yaml, json, toml or csv [default: from the extension of <output>]
+        #[structopt(short, long)]
+        format: Option<TextFormat>,
+    },
+
+    /// Convert a yaml, json, toml or csv file to .sli
+    Import {
+        input: PathBuf,
+        output: PathBuf,
+
+        /// yaml, json, toml or csv [default: detected from the contents of <input>]
+        #[structopt(short, long)]
+        format: Option<TextFormat>,
+    },
+
+    /// Print the format of a file, detected from its contents
+    Detect {
+        file: PathBuf,
+    },
+
+    /// Print every entry of a .sli file in file order
+    Dump {
+        file: PathBuf,
+    },
+
+    /// Print the entry for a tone
+    Get {
+        file: PathBuf,
+
+        /// A label, 0x-prefixed hash or decimal hash
+        tone: Tone,
+    },
+
+    /// Change the ids of an existing tone
+    Set {
+        file: PathBuf,
+        tone: Tone,
+
+        #[structopt(long)]
+        nus3bank_id: Option<u32>,
+
+        #[structopt(long)]
+        tone_id: Option<u32>,
+
+        #[structopt(flatten)]
+        edit: EditArgs,
+    },
+
+    /// Add a new tone
+    Add {
+        file: PathBuf,
+        tone: Tone,
+
+        #[structopt(long)]
+        nus3bank_id: u32,
+
+        #[structopt(long)]
+        tone_id: u32,
+
+        #[structopt(flatten)]
+        edit: EditArgs,
+    },
+
+    /// Remove a tone
+    Remove {
+        file: PathBuf,
+        tone: Tone,
+
+        #[structopt(flatten)]
+        edit: EditArgs,
+    },
+
+    /// Give an existing tone a new name, keeping its ids and position
+    Rename {
+        file: PathBuf,
+        from: Tone,
+        to: Tone,
+
+        #[structopt(flatten)]
+        edit: EditArgs,
+    },
+
+    /// Exchange the ids of two tones, so each plays the other's sound
+    Swap {
+        file: PathBuf,
+        a: Tone,
+        b: Tone,
+
+        #[structopt(flatten)]
+        edit: EditArgs,
+    },
+
+    /// Add a new tone playing the same sound as an existing one
+    Clone {
+        file: PathBuf,
+        src: Tone,
+        new: Tone,
+
+        #[structopt(flatten)]
+        edit: EditArgs,
+    },
+
+    /// Add new songs with ids no other tone uses, printing the ids each was given
+    AddSong {
+        file: PathBuf,
+
+        #[structopt(name = "label", required = true)]
+        songs: Vec<String>,
+
+        #[structopt(flatten)]
+        edit: EditArgs,
+    },
+
+    /// Print the ranges of nus3bank_ids and tone_ids no tone uses
+    FreeIds {
+        file: PathBuf,
+    },
+
+    /// Show the entries added, removed, modified or moved between two .sli files
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+
+        /// Output format, either text or json
+        #[structopt(short, long, default_value = "text")]
+        format: DiffFormat,
+    },
+
+    /// Check a .sli file for problems, exiting with an error if any are found
+    Validate {
+        file: PathBuf,
+    },
+
+    /// Print an annotated hex dump of a .sli file, marking entries with problems with !
+    Inspect {
+        file: PathBuf,
+    },
+
+    /// Rewrite a damaged .sli file with every entry that could still be read
+    Repair {
+        file: PathBuf,
+
+        #[structopt(flatten)]
+        edit: EditArgs,
+    },
+
+    /// Print the hash of each label
+    Hash {
+        #[structopt(name = "label", required = true)]
+        strings: Vec<String>,
+    },
+
+    /// Combine several mods' .sli files, each a modified copy of the same vanilla file
+    Merge {
+        /// The unmodified .sli file every mod was made from
+        base: PathBuf,
+
+        /// The modified .sli file of each mod, later mods win conflicts with --last-wins
+        #[structopt(required = true)]
+        mods: Vec<PathBuf>,
+
+        #[structopt(short, long)]
+        out: PathBuf,
+
+        /// Resolve conflicts in favor of the mod given last instead of failing
+        #[structopt(long, conflicts_with = "priority")]
+        last_wins: bool,
+
+        /// Resolve conflicts in favor of the mod listed first, mods are named by their path
+        #[structopt(long, use_delimiter = true)]
+        priority: Vec<String>,
+    },
+
+    /// Three-way merge for use as a git merge driver, writing the result over <ours>
+    ///
+    /// Configure with `git config merge.sli.driver "sound-label-info merge-driver %O %A %B"`
+    /// and `*.sli merge=sli` in .gitattributes
+    MergeDriver {
+        base: PathBuf,
+        ours: PathBuf,
+        theirs: PathBuf,
+    },
+
+    /// Print one line per entry, sorted by label, for use as a git textconv
+    ///
+    /// Configure with `git config diff.sli.textconv "sound-label-info textconv"` and
+    /// `*.sli diff=sli` in .gitattributes
+    Textconv {
+        file: PathBuf,
+    },
+}
+
+/// Options shared by the commands which edit a .sli file
+#[derive(StructOpt)]
+struct EditArgs {
+    /// Write the result here instead of overwriting <file>
+    #[structopt(short, long)]
+    out: Option<PathBuf>,
+
+    /// Print the change without writing anything
+    #[structopt(long)]
+    dry_run: bool,
+}
+
+impl Command {
+    /// Every file the command reads, which may include `-` for stdin
+    fn inputs(&self) -> Vec<&Path> {
+        match self {
+            Command::ToYaml { input, .. }
+            | Command::ToSli { input, .. }
+            | Command::Export { input, .. }
+            | Command::Import { input, .. } => vec![input],
+            Command::Detect { file }
+            | Command::Dump { file }
+            | Command::Get { file, .. }
+            | Command::Set { file, .. }
+            | Command::Add { file, .. }
+            | Command::Remove { file, .. }
+            | Command::Rename { file, .. }
+            | Command::Swap { file, .. }
+            | Command::Clone { file, .. }
+            | Command::AddSong { file, .. }
+            | Command::FreeIds { file }
+            | Command::Validate { file }
+            | Command::Inspect { file }
+            | Command::Repair { file, .. }
+            | Command::Textconv { file } => vec![file],
+            Command::Diff { old, new, .. } => vec![old, new],
+            Command::Hash { .. } => Vec::new(),
+            Command::Merge { base, mods, .. } => std::iter::once(base).chain(mods).map(PathBuf::as_path).collect(),
+            Command::MergeDriver { base, ours, theirs } => vec![base, ours, theirs],
+        }
+    }
+}
+
+/// A tone given on the command line, keeping the label it was hashed from for messages
+struct Tone {
+    hash: Hash40,
+    label: Option<String>,
+}
+
+impl Tone {
+    fn name<'a>(&'a self, labels: &'a Labels) -> Cow<'a, str> {
+        match &self.label {
+            Some(label) => Cow::Borrowed(label),
+            None => labels.resolve_or_hex(self.hash),
+        }
+    }
+
+    /// Make sure a tone named by its label displays as that label, even if it is not a known one
+    fn add_label(&self, labels: &mut Labels) {
+        if let Some(label) = &self.label {
+            labels.insert(label);
+        }
+    }
+}
+
+impl FromStr for Tone {
+    type Err = ParseHash40Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hash: Hash40 = s.parse()?;
+        let label = if hash == Hash40::from_label(s) { Some(s.to_owned()) } else { None };
+
+        Ok(Tone { hash, label })
+    }
+}
+
+/// A text format .sli files can be converted to and from
+#[derive(Clone, Copy)]
+enum TextFormat {
+    Yaml,
+    Json,
+    Toml,
+    Csv,
+}
+
+/// The header version given to files imported from csv, which has nowhere to store one
+const CSV_VERSION: u32 = 1;
+
+impl TextFormat {
+    fn name(self) -> &'static str {
+        match self {
+            TextFormat::Yaml => "yaml",
+            TextFormat::Json => "json",
+            TextFormat::Toml => "toml",
+            TextFormat::Csv => "csv",
+        }
+    }
+
+    /// Use the format given on the command line, or else guess it from the extension of `path`
+    fn resolve(format: Option<Self>, path: &Path) -> CliResult<Self> {
+        if let Some(format) = format {
+            return Ok(format)
+        }
+
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(|extension| extension.to_ascii_lowercase().parse().ok())
+            .ok_or_else(|| CliError::error(format!(
+                "can't tell the format of {} from its extension, pass --format", path.display()
+            )))
+    }
+
+    fn serialize(self, sli_file: &SliFile, labels: &Labels) -> Result<String, Error> {
+        match self {
+            TextFormat::Yaml => serde_yaml::to_string(&sli_file.with_labels(labels)).map_err(Error::serde),
+            TextFormat::Json => {
+                serde_json::to_string_pretty(&sli_file.with_labels(labels))
+                    .map(|json| json + "\n")
+                    .map_err(Error::serde)
+            }
+            TextFormat::Toml => toml::to_string(&sli_file.with_labels(labels)).map_err(Error::serde),
+            TextFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(Vec::new());
+                for entry in sli_file.entries() {
+                    writer.serialize(WithLabels::new(entry, labels)).map_err(Error::serde)?;
+                }
+                let bytes = writer.into_inner().map_err(Error::serde)?;
+
+                String::from_utf8(bytes).map_err(Error::serde)
+            }
+        }
+    }
+
+    fn deserialize(self, contents: &str) -> Result<SliFile, Error> {
+        match self {
+            TextFormat::Yaml => serde_yaml::from_str(contents).map_err(Error::serde),
+            TextFormat::Json => serde_json::from_str(contents).map_err(Error::serde),
+            TextFormat::Toml => toml::from_str(contents).map_err(Error::serde),
+            TextFormat::Csv => {
+                let entries = csv::ReaderBuilder::new()
+                    .trim(csv::Trim::All)
+                    .from_reader(contents.as_bytes())
+                    .deserialize()
+                    .collect::<Result<Vec<Entry>, _>>()
+                    .map_err(Error::serde)?;
+
+                Ok(SliFile::new(CSV_VERSION, entries))
+            }
+        }
+    }
+}
+
+impl FromStr for TextFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "yaml" | "yml" => Ok(TextFormat::Yaml),
+            "json" => Ok(TextFormat::Json),
+            "toml" => Ok(TextFormat::Toml),
+            "csv" => Ok(TextFormat::Csv),
+            _ => Err(format!("unknown format {:?}, expected yaml, json, toml or csv", s)),
+        }
+    }
+}
+
+enum DiffFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for DiffFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(DiffFormat::Text),
+            "json" => Ok(DiffFormat::Json),
+            _ => Err(format!("unknown diff format {:?}, expected text or json", s)),
+        }
+    }
+}
+
+/// An error to print before exiting with `code`
+struct CliError {
+    message: String,
+    code: i32,
 }
 
+impl CliError {
+    /// The command could not run
+    fn error<S: Into<String>>(message: S) -> Self {
+        CliError { message: message.into(), code: EXIT_ERROR }
+    }
+
+    /// The command ran but found a problem
+    fn failure<S: Into<String>>(message: S) -> Self {
+        CliError { message: message.into(), code: EXIT_FAILURE }
+    }
+}
+
+type CliResult<T = ()> = Result<T, CliError>;
+
 fn main() {
-    let args = Args::from_args();
+    let args = match Args::from_args_safe() {
+        Ok(args) => args,
+        Err(err) => match err.kind {
+            ErrorKind::HelpDisplayed | ErrorKind::VersionDisplayed => err.exit(),
+            _ => {
+                eprintln!("{}", err.message);
+                process::exit(EXIT_ERROR);
+            }
+        },
+    };
+
+    if let Err(err) = run(args) {
+        eprintln!("error: {}", err.message);
+        process::exit(err.code);
+    }
+}
+
+fn run(args: Args) -> CliResult {
+    let labels = args.labels.as_deref();
+    let stdin_inputs = args.command.inputs().into_iter().filter(|path| is_stdio(path)).count();
+    if stdin_inputs > 1 {
+        return Err(CliError::error("only one input can be read from stdin"))
+    }
+    if labels.is_some_and(is_stdio) && stdin_inputs > 0 {
+        return Err(CliError::error("--labels - can't be used while another input is also read from stdin"))
+    }
+
+    let limits = ReadLimits {
+        reject_unknown_versions: args.strict_version,
+        ..Default::default()
+    };
+
+    match args.command {
+        Command::ToYaml { input, output } => export(&input, &output, TextFormat::Yaml, &limits, labels),
+        Command::ToSli { input, output } => import(&input, &output, Some(TextFormat::Yaml)),
+        Command::Export { input, output, format } => {
+            export(&input, &output, TextFormat::resolve(format, &output)?, &limits, labels)
+        }
+        Command::Import { input, output, format } => import(&input, &output, format),
+        Command::Detect { file } => {
+            let format = detect_format(&file, &read_input(&file)?)?;
+            write_stdout(format!("{}\n", format))
+        }
+        Command::Dump { file } => dump(&file, &limits, labels),
+        Command::Get { file, tone } => get(&file, &tone, &limits, labels),
+        Command::Set { file, tone, nus3bank_id, tone_id, edit } => {
+            set(&file, &tone, nus3bank_id, tone_id, &edit, &limits, labels)
+        }
+        Command::Add { file, tone, nus3bank_id, tone_id, edit } => {
+            add(&file, &tone, nus3bank_id, tone_id, &edit, &limits, labels)
+        }
+        Command::Remove { file, tone, edit } => remove(&file, &tone, &edit, &limits, labels),
+        Command::Rename { file, from, to, edit } => rename(&file, &from, &to, &edit, &limits, labels),
+        Command::Swap { file, a, b, edit } => swap(&file, &a, &b, &edit, &limits, labels),
+        Command::Clone { file, src, new, edit } => clone(&file, &src, &new, &edit, &limits, labels),
+        Command::AddSong { file, songs, edit } => add_song(&file, &songs, &edit, &limits, labels),
+        Command::FreeIds { file } => {
+            let free_ids = open_sli(&file, &limits)?.free_ids();
+            write_stdout(format!("{}\n", free_ids))
+        }
+        Command::Diff { old, new, format } => diff(&old, &new, &format, &limits, labels),
+        Command::Validate { file } => validate(&file, &limits, labels),
+        Command::Inspect { file } => inspect(&file, labels),
+        Command::Repair { file, edit } => repair(&file, &edit),
+        Command::Hash { strings } => {
+            let out: String = strings.iter()
+                .map(|label| format!("{} {}\n", Hash40::from_label(label), label))
+                .collect();
+
+            write_stdout(out)
+        }
+        Command::Merge { base, mods, out, last_wins, priority } => {
+            let policy = if last_wins {
+                MergePolicy::LastWins
+            } else if !priority.is_empty() {
+                MergePolicy::Priority(priority)
+            } else {
+                MergePolicy::Fail
+            };
+
+            merge(&base, &mods, &out, &policy, &limits, labels)
+        }
+        Command::MergeDriver { base, ours, theirs } => merge_driver(&base, &ours, &theirs, &limits, labels),
+        Command::Textconv { file } => textconv(&file, &limits, labels),
+    }
+}
+
+/// Whether a path given on the command line means stdin or stdout
+fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Read the whole of an input path, with `-` meaning stdin
+fn read_input(path: &Path) -> CliResult<Vec<u8>> {
+    let result = if is_stdio(path) {
+        let mut bytes = Vec::new();
+        io::stdin().lock().read_to_end(&mut bytes).map(|_| bytes)
+    } else {
+        fs::read(path)
+    };
+
+    result.map_err(|err| CliError::error(format!("failed to read {}: {}", path.display(), err)))
+}
+
+/// Write the whole of an output path, with `-` meaning stdout
+fn write_output(path: &Path, bytes: &[u8]) -> CliResult {
+    if is_stdio(path) {
+        return write_stdout(bytes)
+    }
+
+    fs::write(path, bytes)
+        .map_err(|err| CliError::error(format!("failed to write {}: {}", path.display(), err)))
+}
+
+fn detect_format(path: &Path, bytes: &[u8]) -> CliResult<Format> {
+    detect::detect(bytes)
+        .map_err(|err| CliError::error(format!("failed to detect the format of {}: {}", path.display(), err)))
+}
+
+/// Read a .sli file, warning about an unknown header version or failing with `--strict-version`
+fn open_sli(path: &Path, limits: &ReadLimits) -> CliResult<SliFile> {
+    let sli_file = read_sli(path, limits)?;
+    if !sli_file.has_known_version() {
+        eprintln!("warning: {} has unknown header version {}", path.display(), sli_file.version());
+    }
+
+    Ok(sli_file)
+}
+
+/// Read a .sli file without warning about its version, for commands which report it themselves
+fn read_sli(path: &Path, limits: &ReadLimits) -> CliResult<SliFile> {
+    let bytes = read_input(path)?;
+
+    SliFile::read_with_limits(&mut Cursor::new(&bytes), limits).map_err(|err| {
+        let reason = match err {
+            Error::BadMagic { .. } => match detect::detect(&bytes) {
+                Ok(format) => format!("not a .sli file, it looks like {}, convert it with import", format),
+                Err(err) => format!("not a .sli file, {}", err),
+            },
+            err @ Error::CountMismatch { .. } => {
+                format!("{}, recover the complete entries with repair", err)
+            }
+            err => err.to_string(),
+        };
 
-    match SliFile::open(&args.in_file) {
-        Ok(sli_file) => {
-            let _ = sound_label_info::set_labels(
-                args.labels.as_deref().unwrap_or(Path::new("Hashes.txt"))
-            );
+        CliError::error(format!("failed to read {}: {}", path.display(), reason))
+    })
+}
+
+/// Save over `path` atomically, or write to stdout for `-`
+fn save_sli(sli_file: &SliFile, path: &Path) -> CliResult {
+    if is_stdio(path) {
+        return write_stdout(sli_file.to_bytes())
+    }
+
+    sli_file.save_atomic(path)
+        .map_err(|err| CliError::error(format!("failed to write {}", err)))
+}
 
-            fs::write(&args.out_file, serde_yaml::to_string(&sli_file).unwrap()).unwrap();
+fn write_stdout<B: AsRef<[u8]>>(bytes: B) -> CliResult {
+    match io::stdout().lock().write_all(bytes.as_ref()) {
+        Err(err) if err.kind() != io::ErrorKind::BrokenPipe => {
+            Err(CliError::error(format!("failed to write to stdout: {}", err)))
         }
-        Err(sound_label_info::Error::BadMagic { .. }) => {
-            // Magic doesn't match, should be yaml file
+        _ => Ok(()),
+    }
+}
 
-            let contents = fs::read_to_string(&args.in_file).unwrap();
-            let sli_file: SliFile = serde_yaml::from_str(&contents).unwrap();
+fn format_entry<L: LabelResolver>(labels: &L, entry: &Entry) -> String {
+    format!(
+        "{} nus3bank_id={} tone_id={}",
+        labels.resolve_or_hex(entry.tone_name), entry.nus3bank_id, entry.tone_id
+    )
+}
 
-            sli_file.save(&args.out_file).unwrap();
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn export(input: &Path, output: &Path, format: TextFormat, limits: &ReadLimits, labels: Option<&Path>) -> CliResult {
+    let sli_file = open_sli(input, limits)?;
+    let labels = load_labels(labels)?;
+
+    if let TextFormat::Csv = format {
+        if sli_file.version() != CSV_VERSION {
+            eprintln!("warning: csv has no version, header version {} will be lost", sli_file.version());
+        }
+        if !sli_file.trailing().is_empty() {
+            eprintln!("warning: csv has no trailing bytes, the {} after the last entry will be lost", sli_file.trailing().len());
+        }
+    }
+
+    let text = format.serialize(&sli_file, &labels)
+        .map_err(|err| CliError::error(format!("failed to serialize {}: {}", format.name(), err)))?;
+
+    write_output(output, text.as_bytes())
+}
+
+fn import(input: &Path, output: &Path, format: Option<TextFormat>) -> CliResult {
+    let bytes = read_input(input)?;
+    let format = match format {
+        Some(format) => format,
+        None => match detect_format(input, &bytes)? {
+            Format::Sli => return Err(CliError::error(format!("{} is already a .sli file", input.display()))),
+            Format::Yaml => TextFormat::Yaml,
+            Format::Json => TextFormat::Json,
+            Format::Toml => TextFormat::Toml,
+            Format::Csv => TextFormat::Csv,
         },
+    };
+
+    let contents = String::from_utf8(bytes)
+        .map_err(|err| CliError::error(format!("failed to read {}: {}", input.display(), err)))?;
+
+    let sli_file = format.deserialize(&contents)
+        .map_err(|err| CliError::error(format!("failed to parse {} as {}: {}", input.display(), format.name(), err)))?;
+
+    save_sli(&sli_file, output)
+}
+
+fn dump(path: &Path, limits: &ReadLimits, labels: Option<&Path>) -> CliResult {
+    let sli_file = open_sli(path, limits)?;
+    let labels = load_labels(labels)?;
+
+    let mut out = format!("version {}, {} entries\n", sli_file.version(), sli_file.entries().len());
+    for (i, entry) in sli_file.entries().iter().enumerate() {
+        out += &format!("{:>5} {}\n", i, format_entry(&labels, entry));
+    }
+    if !sli_file.trailing().is_empty() {
+        out += &format!("{} trailing bytes: {}\n", sli_file.trailing().len(), to_hex(sli_file.trailing()));
+    }
+
+    write_stdout(out)
+}
+
+fn get(path: &Path, tone: &Tone, limits: &ReadLimits, labels: Option<&Path>) -> CliResult {
+    let sli_file = open_sli(path, limits)?;
+    let labels = load_labels(labels)?;
+
+    match sli_file.get(tone.hash) {
+        Some(entry) => write_stdout(format!("{}\n", format_entry(&labels, entry))),
+        None => Err(CliError::failure(format!("no entry for {}", tone.name(&labels)))),
+    }
+}
+
+fn set(path: &Path, tone: &Tone, nus3bank_id: Option<u32>, tone_id: Option<u32>, edit: &EditArgs, limits: &ReadLimits, labels: Option<&Path>) -> CliResult {
+    if nus3bank_id.is_none() && tone_id.is_none() {
+        return Err(CliError::error("nothing to set, pass --nus3bank-id and/or --tone-id"))
+    }
+
+    let original = open_sli(path, limits)?;
+    let mut labels = load_labels(labels)?;
+    tone.add_label(&mut labels);
+
+    let mut sli_file = original.clone();
+    let entry = sli_file.get_mut(tone.hash)
+        .ok_or_else(|| CliError::failure(format!("no entry for {}", tone.name(&labels))))?;
+    if let Some(nus3bank_id) = nus3bank_id {
+        entry.nus3bank_id = nus3bank_id;
+    }
+    if let Some(tone_id) = tone_id {
+        entry.tone_id = tone_id;
+    }
+
+    finish_edit(&original, &sli_file, path, edit, &labels)
+}
+
+fn add(path: &Path, tone: &Tone, nus3bank_id: u32, tone_id: u32, edit: &EditArgs, limits: &ReadLimits, labels: Option<&Path>) -> CliResult {
+    let original = open_sli(path, limits)?;
+    let mut labels = load_labels(labels)?;
+    tone.add_label(&mut labels);
+
+    if original.contains(tone.hash) {
+        return Err(CliError::failure(format!("{} already exists, use set to change it", tone.name(&labels))))
+    }
+    let mut sli_file = original.clone();
+    sli_file.insert_or_replace(Entry { tone_name: tone.hash, nus3bank_id, tone_id });
+
+    finish_edit(&original, &sli_file, path, edit, &labels)
+}
+
+fn remove(path: &Path, tone: &Tone, edit: &EditArgs, limits: &ReadLimits, labels: Option<&Path>) -> CliResult {
+    let original = open_sli(path, limits)?;
+    let mut labels = load_labels(labels)?;
+    tone.add_label(&mut labels);
+
+    let mut sli_file = original.clone();
+    if sli_file.remove(tone.hash).is_none() {
+        return Err(CliError::failure(format!("no entry for {}", tone.name(&labels))))
+    }
+
+    finish_edit(&original, &sli_file, path, edit, &labels)
+}
+
+fn rename(path: &Path, from: &Tone, to: &Tone, edit: &EditArgs, limits: &ReadLimits, labels: Option<&Path>) -> CliResult {
+    let original = open_sli(path, limits)?;
+    let mut labels = load_labels(labels)?;
+    from.add_label(&mut labels);
+    to.add_label(&mut labels);
+
+    let mut sli_file = original.clone();
+    sli_file.rename(from.hash, to.hash)
+        .map_err(|err| CliError::failure(err.with_labels(&labels).to_string()))?;
+
+    finish_edit(&original, &sli_file, path, edit, &labels)
+}
+
+fn swap(path: &Path, a: &Tone, b: &Tone, edit: &EditArgs, limits: &ReadLimits, labels: Option<&Path>) -> CliResult {
+    let original = open_sli(path, limits)?;
+    let mut labels = load_labels(labels)?;
+    a.add_label(&mut labels);
+    b.add_label(&mut labels);
+
+    let mut sli_file = original.clone();
+    sli_file.swap(a.hash, b.hash)
+        .map_err(|err| CliError::failure(err.with_labels(&labels).to_string()))?;
+
+    finish_edit(&original, &sli_file, path, edit, &labels)
+}
+
+fn clone(path: &Path, src: &Tone, new: &Tone, edit: &EditArgs, limits: &ReadLimits, labels: Option<&Path>) -> CliResult {
+    let original = open_sli(path, limits)?;
+    let mut labels = load_labels(labels)?;
+    src.add_label(&mut labels);
+    new.add_label(&mut labels);
+
+    let mut sli_file = original.clone();
+    sli_file.clone_entry(src.hash, new.hash)
+        .map_err(|err| CliError::failure(err.with_labels(&labels).to_string()))?;
+
+    finish_edit(&original, &sli_file, path, edit, &labels)
+}
+
+fn add_song(path: &Path, songs: &[String], edit: &EditArgs, limits: &ReadLimits, labels: Option<&Path>) -> CliResult {
+    let mut sli_file = open_sli(path, limits)?;
+    let mut labels = load_labels(labels)?;
+    for song in songs {
+        labels.insert(song);
+    }
+
+    let added = sli_file.allocate_ids(songs)
+        .map_err(|err| CliError::failure(err.with_labels(&labels).to_string()))?;
+
+    let out: String = added.iter()
+        .map(|entry| format!("{}\n", format_entry(&labels, entry)))
+        .collect();
+
+    if edit.dry_run {
+        return write_stdout(out)
+    }
+
+    // the file itself goes to stdout, so the ids can't
+    let out_path = edit.out.as_deref().unwrap_or(path);
+    if is_stdio(out_path) {
+        eprint!("{}", out);
+    } else {
+        write_stdout(out)?;
+    }
+
+    save_sli(&sli_file, out_path)
+}
+
+/// Print what an edit changed if this is a dry run, otherwise save the edited file over `path`
+/// or to `--out`
+fn finish_edit(original: &SliFile, edited: &SliFile, path: &Path, edit: &EditArgs, labels: &Labels) -> CliResult {
+    if edit.dry_run {
+        let diff = original.diff(edited);
+        let out = if diff.is_empty() {
+            "no changes\n".to_owned()
+        } else {
+            diff.with_labels(labels).to_string()
+        };
+
+        return write_stdout(out)
+    }
+
+    save_sli(edited, edit.out.as_deref().unwrap_or(path))
+}
+
+fn diff(old: &Path, new: &Path, format: &DiffFormat, limits: &ReadLimits, labels: Option<&Path>) -> CliResult {
+    let diff = open_sli(old, limits)?.diff(&open_sli(new, limits)?);
+
+    let labels = load_labels(labels)?;
+    let out = match format {
+        DiffFormat::Text => diff.with_labels(&labels).to_string(),
+        DiffFormat::Json => {
+            let json = serde_json::to_string_pretty(&diff.with_labels(&labels))
+                .map_err(|err| CliError::error(format!("failed to serialize json: {}", err)))?;
+
+            json + "\n"
+        }
+    };
+
+    write_stdout(out)
+}
+
+fn validate(path: &Path, limits: &ReadLimits, labels: Option<&Path>) -> CliResult {
+    let sli_file = read_sli(path, limits)?;
+
+    let labels = load_labels(labels)?;
+    let diagnostics = sli_file.validate();
+    let out: String = diagnostics.iter()
+        .map(|diagnostic| format!("{}: {}\n", diagnostic.severity(), diagnostic.with_labels(&labels)))
+        .collect();
+    write_stdout(out)?;
+
+    match diagnostics.iter().filter(|diagnostic| diagnostic.is_error()).count() {
+        0 => Ok(()),
+        errors => Err(CliError::failure(format!("{} has {} error(s)", path.display(), errors))),
+    }
+}
+
+fn inspect(path: &Path, labels: Option<&Path>) -> CliResult {
+    let bytes = read_input(path)?;
+    let inspection = inspect::inspect(&bytes)
+        .map_err(|err| CliError::error(format!("failed to read {}: {}", path.display(), err)))?;
+
+    let labels = load_labels(labels)?;
+    write_stdout(inspection.with_labels(&labels).to_string())
+}
+
+fn repair(path: &Path, edit: &EditArgs) -> CliResult {
+    let bytes = read_input(path)?;
+    let recovered = SliFile::recover(&bytes)
+        .map_err(|err| CliError::error(format!("failed to read {}: {}", path.display(), err)))?;
+
+    // on stderr, as the repaired file may be going to stdout
+    if recovered.is_damaged() {
+        for damage in &recovered.damage {
+            eprintln!("{}: {}", path.display(), damage);
+        }
+        eprintln!("recovered {} entries", recovered.file.entries().len());
+    } else {
+        eprintln!("{} is not damaged", path.display());
+    }
+
+    if edit.dry_run {
+        return Ok(())
+    }
+
+    save_sli(&recovered.file, edit.out.as_deref().unwrap_or(path))
+}
+
+fn merge(base: &Path, mods: &[PathBuf], out: &Path, policy: &MergePolicy, limits: &ReadLimits, labels: Option<&Path>) -> CliResult {
+    // an unmatched name would silently rank below every mod instead of winning
+    if let MergePolicy::Priority(priority) = policy {
+        if let Some(name) = priority.iter().find(|name| !mods.iter().any(|path| path.to_string_lossy() == name.as_str())) {
+            return Err(CliError::error(format!("--priority names {}, which is not one of the mods given", name)))
+        }
+    }
+
+    let base = open_sli(base, limits)?;
+    let mod_files = mods.iter()
+        .map(|path| Ok((path.to_string_lossy(), open_sli(path, limits)?)))
+        .collect::<CliResult<Vec<_>>>()?;
+    let mod_files: Vec<_> = mod_files.iter()
+        .map(|(name, file)| (name.as_ref(), file))
+        .collect();
+
+    let labels = load_labels(labels)?;
+    match base.merge_mods(&mod_files, policy) {
+        Ok(merged) => {
+            for conflict in &merged.conflicts {
+                eprintln!("conflict: {}", conflict.with_labels(&labels));
+            }
+
+            save_sli(&merged.file, out)
+        }
         Err(err) => {
-            // Another error occurred, magic matches but failed to parse
-            eprintln!("An error occurred: {}", err);
+            for conflict in &err.conflicts {
+                eprintln!("conflict: {}", conflict.with_labels(&labels));
+            }
+
+            Err(CliError::failure("merge failed, use --last-wins or --priority to resolve conflicts"))
+        }
+    }
+}
+
+fn merge_driver(base: &Path, ours: &Path, theirs: &Path, limits: &ReadLimits, labels: Option<&Path>) -> CliResult {
+    let base_file = open_sli(base, limits)?;
+    let ours_file = open_sli(ours, limits)?;
+    let theirs_file = open_sli(theirs, limits)?;
+
+    match base_file.merge3(&ours_file, &theirs_file) {
+        Ok(merged) => save_sli(&merged.file, ours),
+        Err(err) => {
+            let labels = load_labels(labels)?;
+            for conflict in &err.conflicts {
+                eprintln!("conflict: {}", conflict.with_labels(&labels));
+            }
+
+            Err(CliError::failure(format!("{} conflict(s)", err.conflicts.len())))
         }
     }
 }
+
+fn textconv(path: &Path, limits: &ReadLimits, labels: Option<&Path>) -> CliResult {
+    let sli_file = open_sli(path, limits)?;
+    let labels = load_labels(labels)?;
+
+    let mut lines: Vec<_> = sli_file.entries()
+        .iter()
+        .map(|entry| (labels.resolve_or_hex(entry.tone_name), entry))
+        .collect();
+    lines.sort_by(|(a_name, a), (b_name, b)| {
+        a_name.cmp(b_name)
+            .then(a.tone_name.cmp(&b.tone_name))
+            .then((a.nus3bank_id, a.tone_id).cmp(&(b.nus3bank_id, b.tone_id)))
+    });
+
+    let mut out = format!("version {}\n", sli_file.version());
+    if !sli_file.trailing().is_empty() {
+        out += &format!("trailing {}\n", to_hex(sli_file.trailing()));
+    }
+    for (name, entry) in lines {
+        out += &format!("{} nus3bank_id={} tone_id={}\n", name, entry.nus3bank_id, entry.tone_id);
+    }
+
+    write_stdout(out)
+}
+
+/// Load the built-in labels, layered under the labels file given on the command line or
+/// `Hashes.txt` if it exists. Malformed lines are reported and skipped, but a labels file given
+/// on the command line which can't be read is an error.
+fn load_labels(path: Option<&Path>) -> CliResult<Labels> {
+    let mut labels = Labels::builtin();
+
+    let contents = match path {
+        Some(path) if is_stdio(path) => {
+            let mut contents = String::new();
+            io::stdin().lock().read_to_string(&mut contents).map(|_| contents)
+        }
+        Some(path) => fs::read_to_string(path),
+        None => fs::read_to_string("Hashes.txt"),
+    };
+
+    let contents = match (contents, path) {
+        (Ok(contents), _) => contents,
+        (Err(err), Some(path)) => {
+            return Err(CliError::error(format!("failed to read labels {}: {}", path.display(), err)))
+        }
+        (Err(err), None) if err.kind() == io::ErrorKind::NotFound => return Ok(labels),
+        (Err(err), None) => {
+            eprintln!("warning: failed to read labels Hashes.txt: {}", err);
+            return Ok(labels)
+        }
+    };
+
+    let (user_labels, malformed) = Labels::parse_lossy(&contents);
+    for line in malformed {
+        eprintln!("warning: skipping malformed label on {}", line);
+    }
+    labels.merge(user_labels);
+
+    Ok(labels)
+}
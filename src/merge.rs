@@ -0,0 +1,407 @@
+//! Combining several modified copies of the same [`SliFile`] into one.
+//!
+//! Each mod is compared against the vanilla base with [`SliFile::diff`], and the resulting
+//! changes are applied to the base. Changes to entry order are not merged: [`SliFile::merge_mods`]
+//! keeps the base order and appends entries added by mods in the order the mods are given, while
+//! [`SliFile::merge3`] keeps the order of `ours`.
+//!
+//! The header version and the trailing bytes are merged like a tone, so mods changing them in
+//! different ways are a conflict.
+
+use crate::diff::TrailingHex;
+use crate::{Entry, Hash40, LabelResolver, SliFile, WithLabels};
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// How to resolve mods changing the same tone in different ways
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergePolicy {
+    /// Fail the merge, reporting every conflict
+    Fail,
+
+    /// The mod given last wins
+    LastWins,
+
+    /// Mods named earlier in the list win over those named later. Mods missing from the list lose
+    /// to every listed mod, with the last unlisted mod winning among them.
+    Priority(Vec<String>),
+}
+
+/// The part of a file a change was made to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Target {
+    /// The entry for a tone
+    Tone(Hash40),
+
+    /// The header version
+    Version,
+
+    /// The bytes after the last entry
+    Trailing,
+}
+
+/// What a mod did to a single [`Target`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// Added the entry or changed its ids
+    Set(Entry),
+
+    /// Removed the entry
+    Remove,
+
+    /// Changed the header version
+    Version(u32),
+
+    /// Changed the trailing bytes, an empty list removes them
+    Trailing(Vec<u8>),
+}
+
+/// A change, along with the mod that made it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModChange {
+    pub mod_name: String,
+    pub change: Change,
+}
+
+/// A tone, the version or the trailing bytes changed in different ways by more than one mod
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub target: Target,
+
+    /// Every mod which changed the target, in the order the mods were given
+    pub changes: Vec<ModChange>,
+
+    /// The index into `changes` of the change which was applied, if the policy picked one
+    pub winner: Option<usize>,
+}
+
+/// The result of [`SliFile::merge_mods`] or [`SliFile::merge3`]
+#[derive(Debug)]
+pub struct MergeOutput {
+    pub file: SliFile,
+
+    /// The conflicts the policy resolved
+    pub conflicts: Vec<Conflict>,
+
+}
+
+/// The merge failed under [`MergePolicy::Fail`], or a three-way merge had conflicts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeError {
+    pub conflicts: Vec<Conflict>,
+}
+
+impl MergePolicy {
+    /// Pick the index of the winning change, or `None` to fail the merge
+    fn pick(&self, changes: &[ModChange]) -> Option<usize> {
+        match self {
+            MergePolicy::Fail => None,
+            MergePolicy::LastWins => Some(changes.len() - 1),
+            MergePolicy::Priority(priority) => {
+                let rank = |change: &ModChange| {
+                    priority.iter()
+                        .position(|name| *name == change.mod_name)
+                        .unwrap_or(usize::MAX)
+                };
+
+                // min_by_key keeps the first of equal ranks, so search from the back for last-wins
+                changes.iter()
+                    .enumerate()
+                    .rev()
+                    .min_by_key(|(_, change)| rank(change))
+                    .map(|(i, _)| i)
+            }
+        }
+    }
+}
+
+impl Change {
+    fn apply(&self, file: &mut SliFile, target: Target) {
+        match self {
+            Change::Set(entry) => {
+                file.insert_or_replace(*entry);
+            }
+            Change::Remove => {
+                if let Target::Tone(tone_name) = target {
+                    file.remove(tone_name);
+                }
+            }
+            Change::Version(version) => file.set_version(*version),
+            Change::Trailing(trailing) => file.set_trailing(trailing.clone()),
+        }
+    }
+}
+
+/// Everything changed by any mod relative to `base`, in the order first touched
+fn collect_changes(base: &SliFile, mods: &[(&str, &SliFile)]) -> Vec<(Target, Vec<ModChange>)> {
+    let mut touched: Vec<(Target, Vec<ModChange>)> = Vec::new();
+    let mut positions: HashMap<Target, usize> = HashMap::new();
+    let mut record = |target: Target, mod_name: &str, change: Change| {
+        let i = *positions.entry(target).or_insert_with(|| {
+            touched.push((target, Vec::new()));
+            touched.len() - 1
+        });
+        touched[i].1.push(ModChange { mod_name: mod_name.to_owned(), change });
+    };
+
+    for (mod_name, file) in mods {
+        let diff = base.diff(file);
+        if let Some(version) = diff.version {
+            record(Target::Version, mod_name, Change::Version(version.after));
+        }
+        for entry in diff.removed {
+            record(Target::Tone(entry.tone_name), mod_name, Change::Remove);
+        }
+        for modified in diff.modified {
+            record(Target::Tone(modified.tone_name), mod_name, Change::Set(modified.after));
+        }
+        for entry in diff.added {
+            record(Target::Tone(entry.tone_name), mod_name, Change::Set(entry));
+        }
+        if let Some(trailing) = diff.trailing {
+            record(Target::Trailing, mod_name, Change::Trailing(trailing.after));
+        }
+    }
+
+    touched
+}
+
+fn all_agree(changes: &[ModChange]) -> bool {
+    changes.iter().all(|change| change.change == changes[0].change)
+}
+
+impl SliFile {
+    /// Merge several modified copies of this file, treating this file as the unmodified base
+    pub fn merge_mods(&self, mods: &[(&str, &SliFile)], policy: &MergePolicy) -> Result<MergeOutput, MergeError> {
+        let mut merged = self.clone();
+        let mut conflicts = Vec::new();
+        let mut failed = false;
+        for (target, changes) in collect_changes(self, mods) {
+            let agreed = all_agree(&changes);
+            let winner = if agreed {
+                Some(0)
+            } else {
+                let winner = policy.pick(&changes);
+                failed |= winner.is_none();
+                winner
+            };
+
+            if let Some(winner) = winner {
+                changes[winner].change.apply(&mut merged, target);
+            }
+
+            if !agreed {
+                conflicts.push(Conflict { target, changes, winner });
+            }
+        }
+
+        if failed {
+            return Err(MergeError { conflicts })
+        }
+
+        Ok(MergeOutput { file: merged, conflicts })
+    }
+
+    /// Three-way merge two modified copies of this file, keyed by `tone_name`.
+    ///
+    /// The result starts from `ours`, keeping its entry order, with the changes from `theirs`
+    /// applied on top. Fails if both sides changed the same tone, the version or the trailing
+    /// bytes in different ways.
+    pub fn merge3(&self, ours: &SliFile, theirs: &SliFile) -> Result<MergeOutput, MergeError> {
+        let touched = collect_changes(self, &[("ours", ours), ("theirs", theirs)]);
+
+        let conflicts: Vec<_> = touched.iter()
+            .filter(|(_, changes)| !all_agree(changes))
+            .map(|(target, changes)| Conflict {
+                target: *target,
+                changes: changes.clone(),
+                winner: None,
+            })
+            .collect();
+        if !conflicts.is_empty() {
+            return Err(MergeError { conflicts })
+        }
+
+        // changes made by ours, or by both sides alike, are already in place
+        let mut merged = ours.clone();
+        for (target, changes) in touched {
+            if let [ModChange { mod_name, change }] = changes.as_slice() {
+                if mod_name == "theirs" {
+                    change.apply(&mut merged, target);
+                }
+            }
+        }
+
+        Ok(MergeOutput { file: merged, conflicts: Vec::new() })
+    }
+}
+
+impl Conflict {
+    /// Pair this conflict with the labels to use for the `tone_name` when displaying it
+    pub fn with_labels<'a, L: LabelResolver + ?Sized>(&'a self, labels: &'a L) -> WithLabels<'a, Self, L> {
+        WithLabels::new(self, labels)
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Set(entry) => write!(f, "sets nus3bank_id {} tone_id {}", entry.nus3bank_id, entry.tone_id),
+            Change::Remove => write!(f, "removes it"),
+            Change::Version(version) => write!(f, "sets it to {}", version),
+            Change::Trailing(trailing) => write!(f, "sets them to {}", TrailingHex(trailing)),
+        }
+    }
+}
+
+impl<L: LabelResolver + ?Sized> fmt::Display for WithLabels<'_, Conflict, L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let conflict = self.value;
+
+        match conflict.target {
+            Target::Tone(tone_name) => write!(f, "{}:", self.labels.resolve_or_hex(tone_name))?,
+            Target::Version => write!(f, "version:")?,
+            Target::Trailing => write!(f, "trailing bytes:")?,
+        }
+        for (i, change) in conflict.changes.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            write!(f, "{} {} {}", separator, change.mod_name, change.change)?;
+        }
+
+        match conflict.winner {
+            Some(winner) => write!(f, " ({} wins)", conflict.changes[winner].mod_name),
+            None => write!(f, " (unresolved)"),
+        }
+    }
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.with_labels(&()).fmt(f)
+    }
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} conflict(s)", self.conflicts.len())?;
+        for conflict in &self.conflicts {
+            write!(f, "\n  {}", conflict)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for MergeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash40;
+
+    #[test]
+    fn test_merge_mods() {
+        let base = SliFile::new(1, vec![
+            Entry::new(hash40("a"), 1, 0),
+            Entry::new(hash40("b"), 2, 0),
+            Entry::new(hash40("c"), 3, 0),
+        ]);
+        let mod_a = SliFile::new(1, vec![
+            Entry::new(hash40("a"), 1, 5),
+            Entry::new(hash40("b"), 2, 1),
+            Entry::new(hash40("c"), 3, 0),
+        ]);
+        let mod_b = SliFile::new(1, vec![
+            Entry::new(hash40("a"), 1, 0),
+            Entry::new(hash40("b"), 2, 2),
+            Entry::new(hash40("d"), 4, 0),
+        ]);
+        let mods = [("mod_a", &mod_a), ("mod_b", &mod_b)];
+
+        let err = base.merge_mods(&mods, &MergePolicy::Fail).unwrap_err();
+        assert_eq!(err.conflicts.len(), 1);
+        assert_eq!(err.conflicts[0].target, Target::Tone(hash40("b")));
+
+        let merged = base.merge_mods(&mods, &MergePolicy::LastWins).unwrap();
+        assert_eq!(merged.file.entries(), &[
+            Entry::new(hash40("a"), 1, 5),
+            Entry::new(hash40("b"), 2, 2),
+            Entry::new(hash40("d"), 4, 0),
+        ]);
+        assert_eq!(merged.conflicts[0].winner, Some(1));
+
+        let priority = MergePolicy::Priority(vec!["mod_a".to_owned()]);
+        let merged = base.merge_mods(&mods, &priority).unwrap();
+        assert_eq!(merged.file.get(hash40("b")), Some(&Entry::new(hash40("b"), 2, 1)));
+
+        let (mut mod_a, mut mod_b) = (mod_a.clone(), mod_b.clone());
+        mod_a.set_trailing(vec![1]);
+        mod_b.set_version(2);
+        let mods = [("mod_a", &mod_a), ("mod_b", &mod_b)];
+        let merged = base.merge_mods(&mods, &MergePolicy::LastWins).unwrap();
+        assert_eq!((merged.file.version(), merged.file.trailing()), (2, &[1][..]));
+        assert_eq!(merged.conflicts.len(), 1);
+
+        mod_a.set_version(3);
+        mod_b.set_trailing(vec![2]);
+        let mods = [("mod_a", &mod_a), ("mod_b", &mod_b)];
+        let merged = base.merge_mods(&mods, &MergePolicy::LastWins).unwrap();
+        assert_eq!((merged.file.version(), merged.file.trailing()), (2, &[2][..]));
+        let targets: Vec<_> = merged.conflicts.iter().map(|conflict| conflict.target).collect();
+        assert_eq!(targets, [Target::Version, Target::Tone(hash40("b")), Target::Trailing]);
+
+        let merged = base.merge_mods(&mods, &priority).unwrap();
+        assert_eq!((merged.file.version(), merged.file.trailing()), (3, &[1][..]));
+
+        let err = base.merge_mods(&mods, &MergePolicy::Fail).unwrap_err();
+        assert_eq!(err.conflicts.len(), 3);
+        assert_eq!(err.conflicts[2].to_string(), "trailing bytes: mod_a sets them to 01, mod_b sets them to 02 (unresolved)");
+    }
+
+    #[test]
+    fn test_merge3() {
+        let base = SliFile::new(1, vec![
+            Entry::new(hash40("a"), 1, 0),
+            Entry::new(hash40("b"), 2, 0),
+            Entry::new(hash40("c"), 3, 0),
+        ]);
+        let ours = SliFile::new(1, vec![
+            Entry::new(hash40("c"), 3, 0),
+            Entry::new(hash40("a"), 1, 5),
+            Entry::new(hash40("b"), 2, 0),
+        ]);
+        let theirs = SliFile::new(1, vec![
+            Entry::new(hash40("a"), 1, 5),
+            Entry::new(hash40("b"), 2, 0),
+            Entry::new(hash40("d"), 4, 0),
+        ]);
+
+        let merged = base.merge3(&ours, &theirs).unwrap().file;
+        assert_eq!(merged.entries(), &[
+            Entry::new(hash40("a"), 1, 5),
+            Entry::new(hash40("b"), 2, 0),
+            Entry::new(hash40("d"), 4, 0),
+        ]);
+
+        let theirs = SliFile::new(1, vec![
+            Entry::new(hash40("a"), 1, 6),
+            Entry::new(hash40("b"), 2, 0),
+            Entry::new(hash40("c"), 3, 0),
+        ]);
+        let err = base.merge3(&ours, &theirs).unwrap_err();
+        assert_eq!(err.conflicts[0].target, Target::Tone(hash40("a")));
+
+        let (mut ours, mut theirs) = (base.clone(), base.clone());
+        ours.set_version(2);
+        theirs.set_trailing(vec![1]);
+        let merged = base.merge3(&ours, &theirs).unwrap().file;
+        assert_eq!((merged.version(), merged.trailing()), (2, &[1][..]));
+
+        theirs.set_version(3);
+        ours.set_trailing(vec![2]);
+        let err = base.merge3(&ours, &theirs).unwrap_err();
+        let targets: Vec<_> = err.conflicts.iter().map(|conflict| conflict.target).collect();
+        assert_eq!(targets, [Target::Version, Target::Trailing]);
+    }
+}
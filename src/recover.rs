@@ -0,0 +1,131 @@
+//! Salvaging what can be read from damaged `.sli` files, such as ones cut off partway through the
+//! entry table by a bad download or a mod manager.
+
+use crate::view::{self, SliView, ENTRY_SIZE, HEADER_SIZE};
+use crate::{Error, Result, SliFile};
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// The entries which could be read from a damaged file, created by [`SliFile::recover`]
+#[derive(Debug, Clone)]
+pub struct Recovered {
+    /// Every complete entry, with the header fixed to match. Saving it writes a consistent file.
+    pub file: SliFile,
+
+    /// What was wrong with the data, empty if it was read as is
+    pub damage: Vec<Damage>,
+}
+
+/// Something [`SliFile::recover`] had to work around
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Damage {
+    /// The header claims `count` entries, but only `found` are complete, ending at byte `offset`
+    MissingEntries {
+        count: u32,
+        found: usize,
+        offset: u64,
+    },
+
+    /// The data ends `len` bytes into the entry at `index`, which starts at byte `offset`. The
+    /// incomplete entry is dropped.
+    PartialEntry {
+        index: usize,
+        offset: u64,
+        len: usize,
+    },
+}
+
+impl Recovered {
+    pub fn is_damaged(&self) -> bool {
+        !self.damage.is_empty()
+    }
+}
+
+impl fmt::Display for Damage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Damage::MissingEntries { count, found, offset } => {
+                write!(f, "the header claims {} entries, but only {} are complete, ending at byte {}", count, found, offset)
+            }
+            Damage::PartialEntry { index, offset, len } => {
+                write!(f, "entry {} at byte {} is cut off after {} of {} bytes", index, offset, len, ENTRY_SIZE)
+            }
+        }
+    }
+}
+
+impl SliFile {
+    /// Read as many complete entries from `bytes` as it holds, instead of failing when the header
+    /// claims more than that like [`from_bytes`](Self::from_bytes) does.
+    ///
+    /// Only the magic and header have to be intact, anything else missing is reported in
+    /// [`Recovered::damage`]. Undamaged files are read exactly as `from_bytes` would, including
+    /// their trailing bytes.
+    pub fn recover(bytes: &[u8]) -> Result<Recovered> {
+        let count = match SliView::new(bytes) {
+            Ok(view) => {
+                let mut file = SliFile::new(view.version(), view.iter().collect());
+                file.set_trailing(view.trailing().to_vec());
+
+                return Ok(Recovered { file, damage: Vec::new() })
+            }
+            Err(Error::CountMismatch { count, .. }) => count,
+            Err(err) => return Err(err),
+        };
+
+        let table = &bytes[HEADER_SIZE..];
+        let entries: Vec<_> = table.chunks_exact(ENTRY_SIZE).map(view::read_entry).collect();
+        let offset = (HEADER_SIZE + entries.len() * ENTRY_SIZE) as u64;
+
+        let mut damage = vec![Damage::MissingEntries { count, found: entries.len(), offset }];
+        let partial = table.len() % ENTRY_SIZE;
+        if partial != 0 {
+            damage.push(Damage::PartialEntry { index: entries.len(), offset, len: partial });
+        }
+
+        let file = SliFile::new(view::read_u32(bytes, 4), entries);
+
+        Ok(Recovered { file, damage })
+    }
+
+    /// Recover the file at `path`, see [`recover`](Self::recover)
+    pub fn recover_file<P: AsRef<Path>>(path: P) -> Result<Recovered> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|err| Error::from(err).at_path(path))?;
+
+        Self::recover(&bytes).map_err(|err| err.at_path(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hash40, Entry};
+
+    #[test]
+    fn test_recover() {
+        let file = SliFile::new(1, vec![
+            Entry::new(hash40("a01_smb_chijyou"), 1, 0),
+            Entry::new(hash40("a02_smb_chika"), 2, 1),
+            Entry::new(hash40("a03_smb_chijyou"), 3, 2),
+        ]);
+        let bytes = file.to_bytes();
+
+        let recovered = SliFile::recover(&bytes).unwrap();
+        assert!(!recovered.is_damaged());
+        assert_eq!(recovered.file.to_bytes(), bytes);
+
+        let recovered = SliFile::recover(&bytes[..bytes.len() - 20]).unwrap();
+        assert_eq!(recovered.file.entries(), &file.entries()[..1]);
+        assert_eq!(recovered.damage, [
+            Damage::MissingEntries { count: 3, found: 1, offset: 28 },
+            Damage::PartialEntry { index: 1, offset: 28, len: 12 },
+        ]);
+        assert_eq!(SliFile::from_bytes(&recovered.file.to_bytes()).unwrap().entries().len(), 1);
+
+        assert!(matches!(SliFile::recover(&bytes[..8]), Err(Error::Truncated { .. })));
+        assert!(matches!(SliFile::recover(b"SLJ\x00\x01\x00\x00\x00\x00\x00\x00\x00"), Err(Error::BadMagic { .. })));
+    }
+}
@@ -0,0 +1,249 @@
+//! Structural checks for [`SliFile`]s, catching problems before a broken file reaches the game.
+
+use crate::view::{ENTRY_SIZE, HEADER_SIZE};
+use crate::{Hash40, LabelResolver, SliFile, WithLabels};
+
+use std::collections::HashMap;
+use std::fmt;
+
+pub use crate::KNOWN_VERSIONS;
+
+/// Labels longer than this are almost certainly a corrupted hash rather than a real name
+pub const MAX_PLAUSIBLE_LABEL_LENGTH: u8 = 64;
+
+/// How serious a [`Diagnostic`] is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Unusual, but the game may still accept the file
+    Warning,
+
+    /// The file is broken
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem found by [`SliFile::validate`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnostic {
+    /// More than one entry has the same `tone_name`
+    DuplicateToneName {
+        tone_name: Hash40,
+        indices: Vec<usize>,
+    },
+
+    /// More than one tone points at the same sound
+    SharedTone {
+        nus3bank_id: u32,
+        tone_id: u32,
+        tone_names: Vec<Hash40>,
+    },
+
+    /// A `tone_name` whose length byte is zero, which only the empty string hashes to
+    ZeroLengthHash {
+        index: usize,
+        tone_name: Hash40,
+    },
+
+    /// A `tone_name` whose length byte is longer than any real label
+    ImplausibleHashLength {
+        index: usize,
+        tone_name: Hash40,
+    },
+
+    /// A `tone_name` with bits set above the 40 bits a hash can use
+    InvalidHash {
+        index: usize,
+        tone_name: Hash40,
+    },
+
+    /// Entries are not sorted by `tone_name` like the vanilla file, starting at `index`
+    Unsorted {
+        index: usize,
+    },
+
+    /// The header version is not one of [`KNOWN_VERSIONS`]
+    UnknownVersion {
+        version: u32,
+    },
+
+    /// There are `len` bytes after the entry table starting at `offset`, which the game's file
+    /// doesn't have. They are kept when saving.
+    TrailingBytes {
+        offset: u64,
+        len: usize,
+    },
+}
+
+impl Diagnostic {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Diagnostic::DuplicateToneName { .. }
+            | Diagnostic::ZeroLengthHash { .. }
+            | Diagnostic::InvalidHash { .. } => Severity::Error,
+
+            Diagnostic::SharedTone { .. }
+            | Diagnostic::ImplausibleHashLength { .. }
+            | Diagnostic::Unsorted { .. }
+            | Diagnostic::UnknownVersion { .. }
+            | Diagnostic::TrailingBytes { .. } => Severity::Warning,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity() == Severity::Error
+    }
+
+    /// Pair this diagnostic with the labels to use for `tone_name`s when displaying it
+    pub fn with_labels<'a, L: LabelResolver + ?Sized>(&'a self, labels: &'a L) -> WithLabels<'a, Self, L> {
+        WithLabels::new(self, labels)
+    }
+}
+
+impl<L: LabelResolver + ?Sized> fmt::Display for WithLabels<'_, Diagnostic, L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let labels = self.labels;
+        match self.value {
+            Diagnostic::DuplicateToneName { tone_name, indices } => {
+                write!(
+                    f, "tone {} appears {} times, at entries {:?}",
+                    labels.resolve_or_hex(*tone_name), indices.len(), indices
+                )
+            }
+            Diagnostic::SharedTone { nus3bank_id, tone_id, tone_names } => {
+                let names: Vec<_> = tone_names.iter()
+                    .map(|name| labels.resolve_or_hex(*name))
+                    .collect();
+
+                write!(
+                    f, "tones {} all use nus3bank_id {} tone_id {}",
+                    names.join(", "), nus3bank_id, tone_id
+                )
+            }
+            Diagnostic::ZeroLengthHash { index, tone_name } => {
+                write!(f, "entry {} has a zero length tone_name {}", index, tone_name)
+            }
+            Diagnostic::ImplausibleHashLength { index, tone_name } => {
+                write!(
+                    f, "entry {} has a tone_name {} claiming a {} character label",
+                    index, tone_name, tone_name.length()
+                )
+            }
+            Diagnostic::InvalidHash { index, tone_name } => {
+                write!(f, "entry {} has a tone_name {} wider than 40 bits", index, tone_name)
+            }
+            Diagnostic::Unsorted { index } => {
+                write!(f, "entries are not sorted by tone_name, starting at entry {}", index)
+            }
+            Diagnostic::UnknownVersion { version } => {
+                write!(f, "unknown header version {}", version)
+            }
+            Diagnostic::TrailingBytes { offset, len } => {
+                write!(f, "{} unexpected bytes after the last entry, at byte {}", len, offset)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.with_labels(&()).fmt(f)
+    }
+}
+
+impl SliFile {
+    /// Check the file for structural problems, returning every one found
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let entries = self.entries();
+        let mut diagnostics = Vec::new();
+
+        if !self.has_known_version() {
+            diagnostics.push(Diagnostic::UnknownVersion { version: self.version() });
+        }
+
+        if !self.trailing().is_empty() {
+            diagnostics.push(Diagnostic::TrailingBytes {
+                offset: (HEADER_SIZE + entries.len() * ENTRY_SIZE) as u64,
+                len: self.trailing().len(),
+            });
+        }
+
+        let mut by_name: HashMap<Hash40, Vec<usize>> = HashMap::new();
+        let mut by_tone: HashMap<(u32, u32), Vec<Hash40>> = HashMap::new();
+        for (index, entry) in entries.iter().enumerate() {
+            let tone_name = entry.tone_name;
+            if tone_name.as_u64() >> 40 != 0 {
+                diagnostics.push(Diagnostic::InvalidHash { index, tone_name });
+            } else if tone_name.length() == 0 {
+                diagnostics.push(Diagnostic::ZeroLengthHash { index, tone_name });
+            } else if tone_name.length() > MAX_PLAUSIBLE_LABEL_LENGTH {
+                diagnostics.push(Diagnostic::ImplausibleHashLength { index, tone_name });
+            }
+
+            by_name.entry(tone_name).or_default().push(index);
+
+            // duplicated entries are already reported, so only count each name once here
+            let tone_names = by_tone.entry((entry.nus3bank_id, entry.tone_id)).or_default();
+            if !tone_names.contains(&tone_name) {
+                tone_names.push(tone_name);
+            }
+        }
+
+        let mut duplicates: Vec<_> = by_name.into_iter()
+            .filter(|(_, indices)| indices.len() > 1)
+            .collect();
+        duplicates.sort_by_key(|(_, indices)| indices[0]);
+        diagnostics.extend(duplicates.into_iter().map(|(tone_name, indices)| {
+            Diagnostic::DuplicateToneName { tone_name, indices }
+        }));
+
+        let mut shared: Vec<_> = by_tone.into_iter()
+            .filter(|(_, tone_names)| tone_names.len() > 1)
+            .collect();
+        shared.sort_by_key(|(ids, _)| *ids);
+        diagnostics.extend(shared.into_iter().map(|((nus3bank_id, tone_id), tone_names)| {
+            Diagnostic::SharedTone { nus3bank_id, tone_id, tone_names }
+        }));
+
+        if let Some(index) = entries.windows(2).position(|pair| pair[0].tone_name > pair[1].tone_name) {
+            diagnostics.push(Diagnostic::Unsorted { index: index + 1 });
+        }
+
+        diagnostics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hash40, Entry};
+
+    #[test]
+    fn test_validate() {
+        let a = Entry::new(hash40("a01_smb_chijyou"), 1, 0);
+        let b = Entry::new(hash40("a02_smb_chika"), 1, 0);
+        let empty = Entry::new(Hash40(0x1234), 2, 0);
+        let file = SliFile::new(1, vec![a, b, a, empty]);
+
+        let diagnostics = file.validate();
+        assert!(diagnostics.contains(&Diagnostic::DuplicateToneName {
+            tone_name: a.tone_name,
+            indices: vec![0, 2],
+        }));
+        assert!(diagnostics.contains(&Diagnostic::ZeroLengthHash { index: 3, tone_name: empty.tone_name }));
+        assert!(diagnostics.iter().any(|diagnostic| matches!(diagnostic, Diagnostic::SharedTone { .. })));
+        assert!(diagnostics.iter().any(|diagnostic| matches!(diagnostic, Diagnostic::Unsorted { .. })));
+        assert!(!diagnostics.iter().any(|diagnostic| matches!(diagnostic, Diagnostic::UnknownVersion { .. })));
+
+        let mut file = file;
+        file.set_trailing(vec![0; 3]);
+        assert!(file.validate().contains(&Diagnostic::TrailingBytes { offset: 12 + 4 * 16, len: 3 }));
+    }
+}
@@ -0,0 +1,201 @@
+//! Reading and patching `.sli` files in place, without parsing them into an [`SliFile`].
+//!
+//! [`SliView`] checks the header and entry count once up front, after which every entry access is
+//! just a bounds-checked read of the underlying bytes. [`SliViewMut`] can additionally change the
+//! `nus3bank_id` and `tone_id` of entries, which never changes the size of the file.
+//!
+//! [`SliFile`]: crate::SliFile
+
+use crate::detect::SLI_MAGIC;
+use crate::{Entry, Error, Hash40, Result};
+
+use std::convert::TryInto;
+
+/// The size of the magic, version and entry count
+pub const HEADER_SIZE: usize = 12;
+
+/// The size of a single entry
+pub const ENTRY_SIZE: usize = 16;
+
+/// A read-only view of a `.sli` file's bytes
+#[derive(Debug, Clone, Copy)]
+pub struct SliView<'a> {
+    version: u32,
+
+    /// Exactly the entry table, without the header or anything after the last entry
+    entries: &'a [u8],
+
+    /// Anything after the last entry
+    trailing: &'a [u8],
+}
+
+/// A view of a `.sli` file's bytes which can change the ids of entries
+#[derive(Debug)]
+pub struct SliViewMut<'a> {
+    version: u32,
+    entries: &'a mut [u8],
+    trailing: &'a [u8],
+}
+
+/// A single entry in a [`SliViewMut`]
+#[derive(Debug)]
+pub struct EntryMut<'a> {
+    bytes: &'a mut [u8],
+}
+
+/// Check the header, returning the version and the range of the entry table
+fn parse_header(bytes: &[u8]) -> Result<(u32, usize)> {
+    if let Some(magic) = bytes.get(..4) {
+        if magic != SLI_MAGIC {
+            return Err(Error::BadMagic { found: magic.try_into().unwrap() })
+        }
+    }
+
+    if bytes.len() < HEADER_SIZE {
+        return Err(Error::Truncated { entry_index: None, offset: 0 })
+    }
+
+    let version = read_u32(bytes, 4);
+    let count = read_u32(bytes, 8);
+    let available = (bytes.len() - HEADER_SIZE) / ENTRY_SIZE;
+    if count as usize > available {
+        let offset = (HEADER_SIZE + available * ENTRY_SIZE) as u64;
+        return Err(Error::CountMismatch { count, available: available as u64, offset })
+    }
+
+    Ok((version, HEADER_SIZE + count as usize * ENTRY_SIZE))
+}
+
+pub(crate) fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+pub(crate) fn read_entry(bytes: &[u8]) -> Entry {
+    Entry {
+        tone_name: Hash40(u64::from_le_bytes(bytes[..8].try_into().unwrap())),
+        nus3bank_id: read_u32(bytes, 8),
+        tone_id: read_u32(bytes, 12),
+    }
+}
+
+impl<'a> SliView<'a> {
+    /// View `bytes` as a `.sli` file. Bytes after the last entry are available from
+    /// [`trailing`](Self::trailing).
+    pub fn new(bytes: &'a [u8]) -> Result<Self> {
+        let (version, end) = parse_header(bytes)?;
+
+        Ok(SliView { version, entries: &bytes[HEADER_SIZE..end], trailing: &bytes[end..] })
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len() / ENTRY_SIZE
+    }
+
+    /// The bytes after the last entry, see [`SliFile::trailing`]
+    pub fn trailing(&self) -> &'a [u8] {
+        self.trailing
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Decode the entry at `index`
+    pub fn get(&self, index: usize) -> Option<Entry> {
+        let start = index.checked_mul(ENTRY_SIZE)?;
+        self.entries.get(start..start.checked_add(ENTRY_SIZE)?).map(read_entry)
+    }
+
+    /// Find the first entry for the given `tone_name` hash along with its index, by linear scan
+    pub fn find(&self, tone_name: Hash40) -> Option<(usize, Entry)> {
+        self.iter().enumerate().find(|(_, entry)| entry.tone_name == tone_name)
+    }
+
+    /// Decode every entry in file order
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = Entry> + 'a {
+        self.entries.chunks_exact(ENTRY_SIZE).map(read_entry)
+    }
+}
+
+impl<'a> SliViewMut<'a> {
+    /// View `bytes` as a `.sli` file for patching. Bytes after the last entry are left untouched.
+    pub fn new(bytes: &'a mut [u8]) -> Result<Self> {
+        let (version, end) = parse_header(bytes)?;
+        let (entries, trailing) = bytes[HEADER_SIZE..].split_at_mut(end - HEADER_SIZE);
+
+        Ok(SliViewMut { version, entries, trailing })
+    }
+
+    /// Borrow as a read-only view, for decoding and searching entries
+    pub fn as_view(&self) -> SliView<'_> {
+        SliView { version: self.version, entries: self.entries, trailing: self.trailing }
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<EntryMut<'_>> {
+        let start = index.checked_mul(ENTRY_SIZE)?;
+        self.entries.get_mut(start..start.checked_add(ENTRY_SIZE)?).map(|bytes| EntryMut { bytes })
+    }
+
+    /// Find the first entry for the given `tone_name` hash, by linear scan
+    pub fn find_mut(&mut self, tone_name: Hash40) -> Option<EntryMut<'_>> {
+        let (index, _) = self.as_view().find(tone_name)?;
+        self.get_mut(index)
+    }
+}
+
+impl EntryMut<'_> {
+    pub fn get(&self) -> Entry {
+        read_entry(self.bytes)
+    }
+
+    pub fn set_nus3bank_id(&mut self, nus3bank_id: u32) {
+        self.bytes[8..12].copy_from_slice(&nus3bank_id.to_le_bytes());
+    }
+
+    pub fn set_tone_id(&mut self, tone_id: u32) {
+        self.bytes[12..16].copy_from_slice(&tone_id.to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hash40, SliFile};
+
+    #[test]
+    fn test_view() {
+        let file = SliFile::new(1, vec![
+            Entry::new(hash40("a01_smb_chijyou"), 1, 0),
+            Entry::new(hash40("a02_smb_chika"), 2, 1),
+        ]);
+        let mut bytes = file.to_bytes();
+        bytes.extend_from_slice(b"extra");
+
+        let view = SliView::new(&bytes).unwrap();
+        assert_eq!(view.version(), 1);
+        assert_eq!(view.len(), 2);
+        assert_eq!(view.get(1), Some(file.entries()[1]));
+        assert_eq!(view.get(2), None);
+        assert_eq!(view.get(usize::MAX / ENTRY_SIZE), None);
+        assert_eq!(view.trailing(), b"extra");
+        assert_eq!(view.iter().collect::<Vec<_>>(), *file.entries());
+
+        let mut view = SliViewMut::new(&mut bytes).unwrap();
+        assert!(view.get_mut(usize::MAX / ENTRY_SIZE).is_none());
+        let mut entry = view.find_mut(hash40("a02_smb_chika")).unwrap();
+        entry.set_nus3bank_id(7);
+        entry.set_tone_id(8);
+        assert!(bytes.ends_with(b"extra"));
+
+        let patched = SliFile::from_bytes(&bytes).unwrap();
+        assert_eq!((patched.entries()[1].nus3bank_id, patched.entries()[1].tone_id), (7, 8));
+
+        assert!(matches!(SliView::new(&bytes[..30]), Err(Error::CountMismatch { count: 2, available: 1, offset: 28 })));
+        assert!(matches!(SliView::new(b"SLJ\x00\x01\x00\x00\x00\x00\x00\x00\x00"), Err(Error::BadMagic { .. })));
+        assert!(matches!(SliView::new(b"SLI\x00\x01"), Err(Error::Truncated { entry_index: None, offset: 0 })));
+    }
+}